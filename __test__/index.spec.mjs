@@ -1,5 +1,5 @@
 import test from 'ava'
-import {ERPCServer, ERPCTarget} from '../index.js'
+import {ERPCPubSub, ERPCServer, ERPCSocket, ERPCTarget} from '../index.js'
 
 //TODO test sockets
 //TODO test more data types and constellations
@@ -59,4 +59,286 @@ test('test handler calls', async (t) => {
   }, 1000);
 
   await server.run();
-})
\ No newline at end of file
+})
+
+test('test handler errors', async (t) => {
+  const server = new ERPCServer({
+    port: 9989,
+    allowedCorsOrigins: ["*"]
+  }, "http-server", true, "Backend");
+
+  server.registerERPCHandler(() => {
+    const err = new Error("The password is wrong")
+    err.code = "wrongPassword"
+    err.data = {attempts: 3}
+    throw err
+  }, "login")
+
+  server.registerERPCHandler(async () => {
+    throw new TypeError("async failure")
+  }, "login/async")
+
+  setTimeout(() => {
+    server.stop();
+  }, 5000);
+
+  const target = new ERPCTarget({
+    address: "http://localhost",
+    port: 9989
+  }, "http-server")
+
+  setTimeout(async () => {
+    const err = await t.throwsAsync(target.call("login"))
+    t.is(err.message, "The password is wrong")
+    t.is(err.code, "wrongPassword")
+    t.deepEqual(err.data, {attempts: 3})
+
+    const asyncErr = await t.throwsAsync(target.call("login/async"))
+    t.is(asyncErr.message, "async failure")
+    t.is(asyncErr.code, "TypeError")
+
+    const notFound = await t.throwsAsync(target.call("unknown"))
+    t.is(notFound.code, "ERPC_HANDLER_NOT_FOUND")
+  }, 1000);
+
+  await server.run();
+})
+
+test('test unknown target type', (t) => {
+  const err = t.throws(() => new ERPCTarget({
+    address: "http://localhost",
+    port: 9990
+  }, "carrier-pigeon"), {instanceOf: TypeError})
+  t.is(err.message, "Unsupported target type carrier-pigeon")
+})
+
+test('test guard', async (t) => {
+  const server = new ERPCServer({
+    port: 9991,
+    allowedCorsOrigins: ["*"]
+  }, "http-server", true, "Backend");
+
+  server.setGuard(async (metadata) => {
+    if (metadata.identifier === "admin") {
+      const err = new Error("Admins only")
+      err.code = "ERPC_FORBIDDEN"
+      throw err
+    }
+    return {user: "alice"}
+  })
+
+  server.registerERPCHandler(function () {
+    this.setCookie("session=abc; HttpOnly")
+    return this.auth.user
+  }, "whoami")
+
+  setTimeout(() => {
+    server.stop();
+  }, 5000);
+
+  const target = new ERPCTarget({
+    address: "http://localhost",
+    port: 9991
+  }, "http-server")
+
+  setTimeout(async () => {
+    t.is(await target.call("whoami"), "alice")
+
+    const response = await fetch("http://localhost:9991/handlers/whoami", {
+      method: "POST",
+      headers: {"Content-Type": "application/json"},
+      body: "[]"
+    })
+    t.is(response.headers.get("set-cookie"), "session=abc; HttpOnly")
+
+    const err = await t.throwsAsync(target.call("admin"))
+    t.is(err.code, "ERPC_FORBIDDEN")
+  }, 1000);
+
+  await server.run();
+})
+
+test('test binary payloads', async (t) => {
+  const server = new ERPCServer({
+    port: 9992,
+    allowedCorsOrigins: ["*"]
+  }, "http-server", true, "Backend");
+
+  server.registerERPCHandler((file) => {
+    t.true(Buffer.isBuffer(file))
+    return {name: "upload.bin", data: Buffer.from(file).reverse()}
+  }, "upload")
+
+  setTimeout(() => {
+    server.stop();
+  }, 5000);
+
+  const target = new ERPCTarget({
+    address: "http://localhost",
+    port: 9992
+  }, "http-server")
+
+  setTimeout(async () => {
+    const result = await target.call("upload", [new Uint8Array([1, 2, 3])])
+    t.is(result.name, "upload.bin")
+    t.deepEqual(result.data, Buffer.from([3, 2, 1]))
+  }, 1000);
+
+  await server.run();
+})
+
+test('test streaming handlers', async (t) => {
+  const server = new ERPCServer({
+    port: 9993,
+    allowedCorsOrigins: ["*"]
+  }, "http-server", true, "Backend");
+
+  server.registerERPCHandler(async function* (count) {
+    for (let i = 0; i < count; i++) {
+      yield {progress: i}
+    }
+  }, "progress")
+
+  setTimeout(() => {
+    server.stop();
+  }, 5000);
+
+  const http = new ERPCTarget({
+    address: "http://localhost",
+    port: 9993
+  }, "http-server")
+  const socket = new ERPCTarget({
+    address: "http://localhost",
+    port: 9993,
+    role: "Frontend"
+  }, "socket-server")
+
+  setTimeout(async () => {
+    await socket.connect()
+    for (const target of [http, socket]) {
+      const items = []
+      for await (const item of target.stream("progress", [2])) {
+        items.push(item)
+      }
+      t.deepEqual(items, [{progress: 0}, {progress: 1}])
+    }
+    socket.close()
+  }, 1000);
+
+  await server.run();
+})
+
+test('test abort listeners are removed once calls settle', async (t) => {
+  const server = new ERPCServer({
+    port: 9994,
+    allowedCorsOrigins: ["*"]
+  }, "http-server", true, "Backend");
+
+  server.registerERPCHandler((v) => v, "echo")
+
+  setTimeout(() => {
+    server.stop();
+  }, 5000);
+
+  const target = new ERPCTarget({
+    address: "http://localhost",
+    port: 9994
+  }, "http-server")
+
+  setTimeout(async () => {
+    const controller = new AbortController()
+    const listeners = new Set()
+    const {signal} = controller
+    const addEventListener = signal.addEventListener.bind(signal)
+    const removeEventListener = signal.removeEventListener.bind(signal)
+    signal.addEventListener = (type, listener, options) => {
+      listeners.add(listener)
+      addEventListener(type, listener, options)
+    }
+    signal.removeEventListener = (type, listener, options) => {
+      listeners.delete(listener)
+      removeEventListener(type, listener, options)
+    }
+
+    for (let i = 0; i < 3; i++) {
+      t.is(await target.call("echo", [i], {signal}), i)
+    }
+    await t.throwsAsync(target.call("unknown", [], {signal}))
+    t.is(listeners.size, 0)
+  }, 1000);
+
+  await server.run();
+})
+
+test('test exports', (t) => {
+  for (const constructor of [ERPCServer, ERPCTarget, ERPCPubSub, ERPCSocket]) {
+    t.is(typeof constructor, "function")
+  }
+  t.true(new ERPCPubSub() instanceof ERPCPubSub)
+  t.throws(() => new ERPCSocket())
+})
+
+test('test shared pub/sub', async (t) => {
+  const pubSub = new ERPCPubSub()
+  const servers = [9995, 9996].map((port) => {
+    const server = new ERPCServer({
+      port,
+      allowedCorsOrigins: ["*"]
+    }, "http-server", true, "Backend");
+    server.setPubSub(pubSub)
+    server.registerERPCHandler((v) => v, "echo")
+    return server
+  })
+
+  t.is(servers[0].publish("news", "update", ["hello"]), 0)
+  t.throws(() => servers[1].subscribe("unknown", "news"))
+
+  setTimeout(() => {
+    servers.forEach((server) => server.stop());
+  }, 5000);
+
+  const target = new ERPCTarget({
+    address: "http://localhost",
+    port: 9996
+  }, "http-server")
+
+  setTimeout(async () => {
+    t.is(await target.call("echo", ["hi"]), "hi")
+    t.is(servers[1].publish("news", "update", ["hello"]), 0)
+  }, 1000);
+
+  await Promise.all(servers.map((server) => server.run()));
+})
+
+test('test socket connections', async (t) => {
+  const server = new ERPCServer({
+    port: 9997,
+    allowedCorsOrigins: ["*"]
+  }, "http-server", true, "Backend");
+
+  const connected = new Promise((resolve) => {
+    server.onSocketConnection((role, socket) => resolve({role, socket}))
+  })
+
+  setTimeout(() => {
+    server.stop();
+  }, 5000);
+
+  const target = new ERPCTarget({
+    address: "http://localhost",
+    port: 9997,
+    role: "Frontend"
+  }, "socket-server")
+
+  setTimeout(async () => {
+    await target.connect()
+    const {role, socket} = await connected
+    t.is(role, "Frontend")
+    t.true(socket instanceof ERPCSocket)
+    t.is(socket.role, "Frontend")
+    t.is(socket.protocolVersion, target.protocolVersion)
+    target.close()
+  }, 1000);
+
+  await server.run();
+})
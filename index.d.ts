@@ -3,13 +3,186 @@
 
 /* auto-generated by NAPI-RS */
 
+/**
+Metadata of an incoming call or socket connection, passed to the guard of the server
+*/
+export interface RequestMetadata {
+  /**
+  The request headers with lowercase names. For calls over a socket these are the headers of the websocket handshake.
+  */
+  headers: Record<string, string>
+  remoteAddress?: string
+  /**
+  The identifier of the called handler. Missing when a socket connects.
+  */
+  identifier?: string
+  /**
+  The role of the socket the call was sent over. Missing for http calls.
+  */
+  role?: string
+  /**
+  The topic a socket subscribes to. Only set when authorizing a subscription.
+  */
+  topic?: string
+  /**
+  The protocol version negotiated with the caller
+  */
+  protocolVersion: number
+}
+/**
+The context a handler is called with, available as `this` in handlers.
+Besides these properties it has `setHeader(name, value)` and `setCookie(cookie)` to add headers to the http response.
+*/
+export interface RequestContext {
+  /**
+  Unique id of the call. For calls over a socket this is the id the caller sent.
+  */
+  requestId: string
+  headers: Record<string, string>
+  remoteAddress?: string
+  identifier?: string
+  role?: string
+  protocolVersion: number
+  /**
+  The auth context returned by the guard of the server
+  */
+  auth?: any
+}
+/**
+The outcome of a call to a single socket, shaped like the results of Promise.allSettled
+*/
+export interface BroadcastResult {
+  socketId: string
+  status: 'fulfilled' | 'rejected'
+  /**
+  The value the call resolved to, if fulfilled
+  */
+  value?: unknown
+  /**
+  The error the call has been rejected with, if rejected
+  */
+  reason?: Error
+}
+/**
+A PEM encoded certificate chain and private key, either as buffers or as file paths
+*/
+export interface TlsOptions {
+  cert?: Buffer
+  key?: Buffer
+  certPath?: string
+  keyPath?: string
+}
 export interface ServerOptions {
   port: number
   allowedCorsOrigins: Array<string>
+  /**
+  The host to listen on, e.g. 0.0.0.0 or ::. Defaults to 127.0.0.1
+  */
+  host?: string
+  /**
+  Socket addresses to listen on, e.g. 0.0.0.0:9988 or [::]:9988. Takes precedence over host and port
+  */
+  addresses?: Array<string>
+  /**
+  Serve https and wss with the given certificate
+  */
+  tls?: TlsOptions
+  /**
+  The maximum size of request bodies and websocket messages in bytes. Defaults to 1 MiB
+  */
+  maxBodySize?: number
+  /**
+  List the registered handlers via GET handlers. Defaults to false
+  */
+  introspection?: boolean
+}
+/**
+Optional information about a handler, listed along with its identifier
+*/
+export interface HandlerMetadata {
+  /**
+  What the handler does, in human readable form
+  */
+  description?: string
+  /**
+  A schema of the parameters the handler expects, e.g. a JSON schema
+  */
+  parameters?: any
+}
+/**
+A registered handler as listed by the server, with its metadata
+*/
+export interface HandlerDescription {
+  identifier: string
+  /**
+  What the handler does, in human readable form
+  */
+  description?: string
+  /**
+  A schema of the parameters the handler expects, e.g. a JSON schema
+  */
+  parameters?: any
 }
 export interface TargetOptions {
   port: number
   address: string
+  /**
+  Time in milliseconds to wait for a response before a call is rejected with a timeout error
+  */
+  timeout?: number
+  /**
+  The encoding of requests to http servers, either "json" (default) or "msgpack"
+  */
+  encoding?: string
+  /**
+  The role socket server targets connect with, i.e. the server endpoint ws/{role}
+  */
+  role?: string
+  /**
+  How socket server targets reconnect after their connection dropped
+  */
+  reconnect?: ReconnectOptions
+  /**
+  Collects calls made in the same tick and sends them as a single batch request
+  */
+  batch?: boolean
+}
+export interface ReconnectOptions {
+  /**
+  Milliseconds before the first reconnection attempt, defaults to 100
+  */
+  initialDelay?: number
+  /**
+  Maximum milliseconds between two attempts, defaults to 30000
+  */
+  maxDelay?: number
+  /**
+  The factor the delay grows by with every failed attempt, defaults to 2
+  */
+  multiplier?: number
+  /**
+  The fraction of the delay which is randomized, between 0 and 1, defaults to 0.5
+  */
+  jitter?: number
+  /**
+  Gives up after this many failed attempts in a row, retries forever by default
+  */
+  maxAttempts?: number
+  /**
+  What happens to calls waiting for a response when the connection drops.
+  "fail" (default) rejects them, "resend" sends them again once reconnected.
+  */
+  inFlight?: string
+}
+export interface CallOptions {
+  /**
+  Time in milliseconds to wait for a response, overrides the timeout of the target
+  */
+  timeout?: number
+  /**
+  Rejects the call with a cancelled error when aborted
+  */
+  signal?: AbortSignal
 }
 export class ERPCServer {
   constructor(options: ServerOptions, serverType: string, enableSockets: boolean, role: string)
@@ -18,10 +191,107 @@ export class ERPCServer {
   */
   run(): Promise<void>
   /**
+  The addresses the server is bound to. Available once run has been called, e.g. to find out the port when listening on port 0
+  */
+  get addresses(): Array<string>
+  /**
+  Replaces the served certificate without restarting the server. New connections use the new certificate.
+  */
+  reloadTls(options: TlsOptions): void
+  /**
+  Attaches metadata like a description or a parameter schema to a registered handler
+  */
+  describeHandler(identifier: string, metadata: HandlerMetadata): void
+  /**
+  The registered handlers with their metadata, ordered by identifier
+  */
+  handlers(): Array<HandlerDescription>
+  /**
+  Calls the handler on every socket connected with the given role.
+  Resolves to the outcome of each call like Promise.allSettled, together with the id of the socket.
+  */
+  broadcast(role: string, methodIdentifier: string, parameters?: Array<unknown> | undefined | null, timeout?: number | undefined | null): Promise<Array<BroadcastResult>>
+  /**
+  Subscribes a socket connected to this server to the topic
+  */
+  subscribe(socketId: string, topic: string): void
+  unsubscribe(socketId: string, topic: string): void
+  /**
+  Sends a notification to every socket subscribed to the topic, including sockets of servers sharing the pub/sub.
+  Returns the number of sockets it has been sent to.
+  */
+  publish(topic: string, methodIdentifier: string, parameters?: Array<unknown> | undefined | null): number
+  /**
+  Calls the handler on every socket subscribed to the topic and resolves to the outcome of each call, like broadcast
+  */
+  publishCall(topic: string, methodIdentifier: string, parameters?: Array<unknown> | undefined | null, timeout?: number | undefined | null): Promise<Array<BroadcastResult>>
+  /**
+  Shares the topic subscriptions with other servers using the same pub/sub. Must be called before run.
+  */
+  setPubSub(pubSub: ERPCPubSub): void
+  /**
   * Stops the server
   */
   stop(): void
 }
+/**
+Keeps track of topic subscriptions. Servers sharing an instance can publish to the sockets of each other.
+*/
+export class ERPCPubSub {
+  constructor()
+}
+/**
+A websocket connection of a client, passed to the socket connection callback of the server
+*/
+export class ERPCSocket {
+  /**
+  Sockets can not be constructed, they are passed to the socket connection callback of the server
+  */
+  constructor()
+  /**
+  Unique id of this connection
+  */
+  get id(): string
+  /**
+  The role the client connected with
+  */
+  get role(): string
+  /**
+  The protocol version negotiated with the client during the handshake
+  */
+  get protocolVersion(): number
+  /**
+  Closes the connection. Defaults to the normal closure code 1000
+  */
+  close(code?: number | undefined | null, reason?: string | undefined | null): void
+}
 export class ERPCTarget {
   constructor(options: TargetOptions, targetType: string)
+  /**
+  Sends a notification, which the receiver handles without responding.
+  Resolves once the notification has been accepted by the server or sent via socket.
+  */
+  notify(methodIdentifier: string, parameters?: Array<unknown> | undefined | null): Promise<void>
+  /**
+  Calls a handler which streams its response, e.g. an async generator, and returns its items as async iterator.
+  Breaking out of a for await loop cancels the call.
+  */
+  stream(methodIdentifier: string, parameters?: Array<unknown> | undefined | null): AsyncIterableIterator<any>
+  /**
+  Connects a socket server target to the ws/{role} endpoint of the server.
+  Resolves once connected, after which the target reconnects on its own whenever the connection drops.
+  */
+  connect(): Promise<void>
+  /**
+  Closes the connection of a socket server target and stops reconnecting
+  */
+  close(): void
+  /**
+  Whether a socket is currently connected to this target
+  */
+  get connected(): boolean
+  /**
+  The protocol version negotiated with the peer of the connected socket, undefined while not connected
+  */
+  get protocolVersion(): number | null
 }
@@ -236,7 +236,9 @@ if (!nativeBinding) {
   throw new Error(`Failed to load native binding`)
 }
 
-const { ERPCServer, ERPCTarget } = nativeBinding
+const { ERPCServer, ERPCPubSub, ERPCSocket, ERPCTarget } = nativeBinding
 
 module.exports.ERPCServer = ERPCServer
+module.exports.ERPCPubSub = ERPCPubSub
+module.exports.ERPCSocket = ERPCSocket
 module.exports.ERPCTarget = ERPCTarget
@@ -0,0 +1,113 @@
+use std::collections::HashMap;
+
+use napi::{bindgen_prelude::ToNapiValue, Env, JsObject, NapiValue};
+
+/**
+  Metadata of an incoming call or socket connection, passed to the guard of the server
+*/
+#[napi(object)]
+pub struct RequestMetadata {
+  /**
+    The request headers with lowercase names. For calls over a socket these are the headers of the websocket handshake.
+  */
+  pub headers: HashMap<String, String>,
+  pub remote_address: Option<String>,
+  /**
+    The identifier of the called handler. Missing when a socket connects.
+  */
+  pub identifier: Option<String>,
+  /**
+    The role of the socket the call was sent over. Missing for http calls.
+  */
+  pub role: Option<String>,
+  /**
+    The topic a socket subscribes to. Only set when authorizing a subscription.
+  */
+  pub topic: Option<String>,
+  /**
+    The protocol version negotiated with the caller
+  */
+  pub protocol_version: u32,
+}
+
+impl From<crate::erpc::context::RequestMetadata> for RequestMetadata {
+  fn from(metadata: crate::erpc::context::RequestMetadata) -> Self {
+    RequestMetadata {
+      headers: metadata.headers,
+      remote_address: metadata.remote_address.map(|v| v.to_string()),
+      identifier: metadata.identifier,
+      role: metadata.role,
+      topic: metadata.topic,
+      protocol_version: metadata.protocol_version,
+    }
+  }
+}
+
+/**
+  The context a handler is called with, available as `this` in handlers.
+  Besides these properties it has `setHeader(name, value)` and `setCookie(cookie)` to add headers to the http response.
+*/
+#[napi(object)]
+pub struct RequestContext {
+  /**
+    Unique id of the call. For calls over a socket this is the id the caller sent.
+  */
+  pub request_id: String,
+  pub headers: HashMap<String, String>,
+  pub remote_address: Option<String>,
+  pub identifier: Option<String>,
+  pub role: Option<String>,
+  pub protocol_version: u32,
+  /**
+    The auth context returned by the guard of the server
+  */
+  pub auth: Option<serde_json::Value>,
+}
+
+impl From<crate::erpc::context::RequestContext> for RequestContext {
+  fn from(context: crate::erpc::context::RequestContext) -> Self {
+    let metadata = RequestMetadata::from(context.metadata);
+    RequestContext {
+      request_id: context.request_id,
+      headers: metadata.headers,
+      remote_address: metadata.remote_address,
+      identifier: metadata.identifier,
+      role: metadata.role,
+      protocol_version: metadata.protocol_version,
+      auth: context.auth,
+    }
+  }
+}
+
+/**
+  Creates the JS object handlers are called with, including the functions to set response headers
+*/
+pub fn to_js_context(
+  env: &Env,
+  context: crate::erpc::context::RequestContext,
+) -> Result<JsObject, napi::Error> {
+  let header_context = context.clone();
+  let cookie_context = context.clone();
+  let mut object = unsafe {
+    JsObject::from_raw(
+      env.raw(),
+      ToNapiValue::to_napi_value(env.raw(), RequestContext::from(context))?,
+    )?
+  };
+
+  let set_header = env.create_function_from_closure("setHeader", move |ctx| {
+    let name = ctx.get::<napi::JsString>(0)?.into_utf8()?.into_owned()?;
+    let value = ctx.get::<napi::JsString>(1)?.into_utf8()?.into_owned()?;
+    header_context.set_header(&name, &value);
+    ctx.env.get_undefined()
+  })?;
+  let set_cookie = env.create_function_from_closure("setCookie", move |ctx| {
+    let cookie = ctx.get::<napi::JsString>(0)?.into_utf8()?.into_owned()?;
+    cookie_context.set_cookie(&cookie);
+    ctx.env.get_undefined()
+  })?;
+  object.set_named_property("setHeader", set_header)?;
+  object.set_named_property("setCookie", set_cookie)?;
+
+  Ok(object)
+}
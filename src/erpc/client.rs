@@ -0,0 +1,298 @@
+use std::time::Duration;
+
+use futures_util::{SinkExt, StreamExt};
+use rand::Rng;
+use tokio::sync::oneshot;
+use tokio_tungstenite::tungstenite::{
+  self,
+  protocol::{frame::coding::CloseCode, CloseFrame},
+  Message,
+};
+
+use super::{
+  error::ERPCError,
+  protocol::{
+    self,
+    encoding::Encoding,
+    socket::{Frame, Pong, ProtocolError, SocketMessage, TAGGED_VERSION},
+  },
+  Socket, SocketEvent,
+};
+
+/**
+  What happens to calls waiting for a response when the connection of a socket server target drops
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum InFlightPolicy {
+  /**
+    The calls fail with a socket closed error
+  */
+  #[default]
+  Fail,
+  /**
+    The calls are sent again once reconnected. Calls made while reconnecting wait for the connection.
+    Streams always fail, as their items might have been handled partially.
+  */
+  Resend,
+}
+
+/**
+  How a socket server target reconnects after its connection dropped
+*/
+#[derive(Debug, Clone)]
+pub struct ReconnectPolicy {
+  /**
+    The delay before the first reconnection attempt
+  */
+  pub initial_delay: Duration,
+  /**
+    The upper bound of the delay between two attempts
+  */
+  pub max_delay: Duration,
+  /**
+    The factor the delay grows by with every failed attempt
+  */
+  pub multiplier: f64,
+  /**
+    The fraction of the delay which is randomized, between 0 and 1, so clients do not reconnect all at once
+  */
+  pub jitter: f64,
+  /**
+    Gives up after this many failed attempts in a row, retries forever when None
+  */
+  pub max_attempts: Option<u32>,
+  pub in_flight: InFlightPolicy,
+}
+
+impl Default for ReconnectPolicy {
+  fn default() -> Self {
+    ReconnectPolicy {
+      initial_delay: Duration::from_millis(100),
+      max_delay: Duration::from_secs(30),
+      multiplier: 2.0,
+      jitter: 0.5,
+      max_attempts: None,
+      in_flight: InFlightPolicy::default(),
+    }
+  }
+}
+
+impl ReconnectPolicy {
+  /**
+    The delay before the given attempt, starting at 0
+  */
+  pub fn delay(&self, attempt: u32) -> Duration {
+    let delay = self
+      .initial_delay
+      .mul_f64(self.multiplier.max(1.0).powi(attempt.min(64) as i32))
+      .min(self.max_delay);
+    let jitter = self.jitter.clamp(0.0, 1.0);
+    delay.mul_f64(1.0 - jitter * rand::thread_rng().gen::<f64>())
+  }
+
+  /**
+    Whether another attempt should be made after the given number of failed attempts
+  */
+  pub fn should_retry(&self, failed_attempts: u32) -> bool {
+    self
+      .max_attempts
+      .map(|v| failed_attempts < v)
+      .unwrap_or(true)
+  }
+}
+
+/**
+  The websocket address to dial for the address of a target, http addresses are dialed as ws.
+  Fails for https and wss addresses, as sockets are opened without TLS support.
+*/
+pub fn websocket_address(address: &str) -> Result<String, ERPCError> {
+  match address.split_once("://") {
+    Some(("https" | "wss", _)) => Err(ERPCError::Transport(format!(
+      "Can not connect to {address}, TLS is not supported for socket server targets"
+    ))),
+    Some(("http", v)) => Ok(format!("ws://{v}")),
+    Some(_) => Ok(address.to_owned()),
+    None => Ok(format!("ws://{address}")),
+  }
+}
+
+/**
+  Opens a websocket connection to the ws/{role} endpoint of an erpc server, see websocket_address
+*/
+pub async fn dial(
+  address: &str,
+  port: u16,
+  role: &str,
+  encoding: Encoding,
+) -> Result<Socket, ERPCError> {
+  let address = websocket_address(address)?;
+  let url = format!(
+    "{address}:{port}/ws/{role}?encoding={}&version={}",
+    encoding.name(),
+    protocol::PROTOCOL_VERSION
+  );
+
+  let (socket, response) =
+    tokio_tungstenite::connect_async(url)
+      .await
+      .map_err(|err| match err {
+        // the server rejected the handshake, e.g. because of the guard
+        tungstenite::Error::Http(response) => {
+          let message = response
+            .body()
+            .as_ref()
+            .map(|v| String::from_utf8_lossy(v).into_owned())
+            .unwrap_or_default();
+          ERPCError::from_status(
+            reqwest::StatusCode::from_u16(response.status().as_u16())
+              .unwrap_or(reqwest::StatusCode::BAD_GATEWAY),
+            role,
+            message,
+          )
+        }
+        err => ERPCError::Transport(err.to_string()),
+      })?;
+  // servers not answering with a version predate the negotiation
+  let version = response
+    .headers()
+    .get(protocol::VERSION_HEADER)
+    .and_then(|v| v.to_str().ok())
+    .and_then(|v| v.trim().parse().ok())
+    .unwrap_or(protocol::MIN_PROTOCOL_VERSION);
+
+  let (mut socket_sender, mut socket_reciever) = socket.split();
+  let (incoming_sender, incoming_reciever) = flume::unbounded::<SocketMessage>();
+  let (outgoing_sender, outgoing_reciever) = flume::unbounded::<SocketMessage>();
+  let (event_sender, event_reciever) = flume::unbounded::<SocketEvent>();
+  let (close_sender, close_reciever) = flume::unbounded::<(u16, String)>();
+  let (reader_done_sender, mut reader_done_reciever) = oneshot::channel::<()>();
+
+  let socket = Socket {
+    id: nanoid::nanoid!(),
+    sender: outgoing_sender,
+    reciever: incoming_reciever,
+    role: role.to_owned(),
+    version,
+    events: event_reciever,
+    closer: close_sender,
+    calls: Default::default(),
+  };
+
+  let reader_event_sender = event_sender.clone();
+  let reader_outgoing_sender = socket.sender.clone();
+  tokio::spawn(async move {
+    let mut close_frame = (None, String::new());
+    loop {
+      let message = match socket_reciever.next().await {
+        Some(Ok(v)) => v,
+        Some(Err(err)) => {
+          reader_event_sender
+            .send(SocketEvent::Error(format!(
+              "Websocket message error: {err}"
+            )))
+            .ok();
+          break;
+        }
+        None => break,
+      };
+
+      let (data, is_binary) = match message {
+        Message::Text(v) => (v.into_bytes(), false),
+        Message::Binary(v) => (v, true),
+        Message::Close(frame) => {
+          if let Some(frame) = frame {
+            close_frame = (Some(frame.code.into()), frame.reason.into_owned());
+          }
+          break;
+        }
+        _ => continue,
+      };
+      let message = match SocketMessage::parse(&data, is_binary, encoding, version) {
+        Ok(v) => v,
+        Err(err) => {
+          eprintln!("Websocket message parse error: {err}");
+          reader_event_sender
+            .send(SocketEvent::Error(format!(
+              "Websocket message parse error: {err}"
+            )))
+            .ok();
+          if version >= TAGGED_VERSION {
+            reader_outgoing_sender
+              .send(SocketMessage::Error(ProtocolError {
+                id: SocketMessage::malformed_id(&data, is_binary, encoding),
+                message: err.to_string(),
+              }))
+              .ok();
+          }
+          continue;
+        }
+      };
+
+      let forwarded = match message {
+        SocketMessage::Ping(ping) => reader_outgoing_sender
+          .send(SocketMessage::Pong(Pong { id: ping.id }))
+          .is_ok(),
+        SocketMessage::Pong(_) => true,
+        message => incoming_sender.send(message).is_ok(),
+      };
+      // the target stopped listening
+      if !forwarded {
+        break;
+      }
+    }
+
+    reader_done_sender.send(()).ok();
+    reader_event_sender
+      .send(SocketEvent::Close {
+        code: close_frame.0,
+        reason: close_frame.1,
+      })
+      .ok();
+  });
+
+  tokio::spawn(async move {
+    loop {
+      let message = tokio::select! {
+        v = outgoing_reciever.recv_async() => match v {
+          Ok(v) => v,
+          // all handles to the socket have been dropped
+          Err(_) => break,
+        },
+        v = close_reciever.recv_async() => {
+          if let Ok((code, reason)) = v {
+            let frame = CloseFrame {
+              code: CloseCode::from(code),
+              reason: reason.into(),
+            };
+            if let Err(err) = socket_sender.send(Message::Close(Some(frame))).await {
+              event_sender
+                .send(SocketEvent::Error(format!("Could not close websocket: {err}")))
+                .ok();
+            }
+          }
+          break;
+        },
+        _ = &mut reader_done_reciever => break,
+      };
+
+      let frame = match message.encode(encoding) {
+        Ok(Frame::Text(v)) => Message::Text(v),
+        Ok(Frame::Binary(v)) => Message::Binary(v),
+        Err(err) => {
+          eprintln!("Could not serialize ws message: {err}");
+          continue;
+        }
+      };
+      if let Err(err) = socket_sender.send(frame).await {
+        event_sender
+          .send(SocketEvent::Error(format!(
+            "Could not send websocket message: {err}"
+          )))
+          .ok();
+        break;
+      }
+    }
+  });
+
+  Ok(socket)
+}
@@ -0,0 +1,127 @@
+use std::{
+  collections::HashMap,
+  net::SocketAddr,
+  sync::{Arc, Mutex},
+};
+
+use warp::http::HeaderMap;
+
+/**
+  Metadata of an incoming call or socket connection
+*/
+#[derive(Clone, Debug, Default)]
+pub struct RequestMetadata {
+  /**
+    The request headers with lowercase names. Repeated headers are joined with a comma.
+    For calls over a socket these are the headers of the websocket handshake.
+  */
+  pub headers: HashMap<String, String>,
+  /**
+    The address of the client, if known
+  */
+  pub remote_address: Option<SocketAddr>,
+  /**
+    The identifier of the called handler. Missing when a socket connects.
+  */
+  pub identifier: Option<String>,
+  /**
+    The role of the socket the call was sent over. Missing for http calls.
+  */
+  pub role: Option<String>,
+  /**
+    The topic a socket subscribes to. Only set when authorizing a subscription.
+  */
+  pub topic: Option<String>,
+  /**
+    The protocol version negotiated with the caller, see protocol::negotiate_version
+  */
+  pub protocol_version: u32,
+}
+
+impl RequestMetadata {
+  pub fn new(headers: &HeaderMap, remote_address: Option<SocketAddr>) -> Self {
+    let mut collected: HashMap<String, String> = HashMap::new();
+    for (name, value) in headers {
+      let value = String::from_utf8_lossy(value.as_bytes());
+      collected
+        .entry(name.as_str().to_owned())
+        .and_modify(|v| {
+          v.push_str(", ");
+          v.push_str(&value);
+        })
+        .or_insert_with(|| value.into_owned());
+    }
+
+    RequestMetadata {
+      headers: collected,
+      remote_address,
+      identifier: None,
+      role: None,
+      topic: None,
+      protocol_version: super::protocol::PROTOCOL_VERSION,
+    }
+  }
+}
+
+/**
+  The context a handler is called with.
+  Handlers registered via register_handler receive it by taking a RequestContext parameter.
+*/
+#[derive(Clone, Debug, Default)]
+pub struct RequestContext {
+  /**
+    Unique id of the call. For calls over a socket this is the id the caller sent.
+  */
+  pub request_id: String,
+  pub metadata: RequestMetadata,
+  /**
+    The auth context returned by the guard of the server
+  */
+  pub auth: Option<serde_json::Value>,
+  /**
+    Headers to add to the response
+  */
+  response_headers: Arc<Mutex<Vec<(String, String)>>>,
+}
+
+impl RequestContext {
+  pub fn new(
+    request_id: String,
+    metadata: RequestMetadata,
+    auth: Option<serde_json::Value>,
+  ) -> Self {
+    RequestContext {
+      request_id,
+      metadata,
+      auth,
+      response_headers: Arc::new(Mutex::new(Vec::new())),
+    }
+  }
+
+  /**
+    Adds a header to the response. Calls over a socket have no response headers, so it is ignored for them.
+  */
+  pub fn set_header(&self, name: &str, value: &str) {
+    if let Ok(mut headers) = self.response_headers.lock() {
+      headers.push((name.to_owned(), value.to_owned()));
+    }
+  }
+
+  /**
+    Adds a Set-Cookie header to the response, e.g. `session=abc; HttpOnly`
+  */
+  pub fn set_cookie(&self, cookie: &str) {
+    self.set_header("set-cookie", cookie);
+  }
+
+  /**
+    The headers set by the handler so far
+  */
+  pub fn response_headers(&self) -> Vec<(String, String)> {
+    self
+      .response_headers
+      .lock()
+      .map(|v| v.clone())
+      .unwrap_or_default()
+  }
+}
@@ -0,0 +1,174 @@
+use super::protocol::ApplicationError;
+use reqwest::StatusCode;
+use std::fmt::Display;
+
+/**
+  An error which can occur while serving or calling erpc handlers
+*/
+#[derive(Debug, Clone)]
+pub enum ERPCError {
+  /**
+    The underlying connection (http or websocket) failed
+  */
+  Transport(String),
+  /**
+    A value could not be serialized or deserialized
+  */
+  Serialization(String),
+  /**
+    There is no handler registered for the contained identifier
+  */
+  HandlerNotFound(String),
+  /**
+    The handler could not process the request
+  */
+  HandlerFailed(String),
+  /**
+    A user defined error thrown by the handler
+  */
+  Application(ApplicationError),
+  /**
+    The call is missing valid credentials
+  */
+  Unauthorized(String),
+  /**
+    The caller is not allowed to make the call
+  */
+  Forbidden(String),
+  /**
+    The request body exceeds the maximum size accepted by the server
+  */
+  PayloadTooLarge(String),
+  /**
+    The request did not complete in time
+  */
+  Timeout,
+  /**
+    The call was cancelled by the caller
+  */
+  Cancelled,
+  /**
+    The socket the request was sent over has been closed
+  */
+  SocketClosed,
+  /**
+    The peer speaks a protocol version which is not supported
+  */
+  IncompatibleVersion(String),
+  /**
+    The TLS certificate or private key could not be read or used
+  */
+  Tls(String),
+  /**
+    Any other error, e.g. a poisoned lock or an invalid server state
+  */
+  Internal(String),
+}
+
+impl ERPCError {
+  /**
+    A stable identifier for the kind of this error, e.g. for branching on errors in JS
+  */
+  pub fn code(&self) -> &'static str {
+    match self {
+      ERPCError::Transport(_) => "ERPC_TRANSPORT",
+      ERPCError::Serialization(_) => "ERPC_SERIALIZATION",
+      ERPCError::HandlerNotFound(_) => "ERPC_HANDLER_NOT_FOUND",
+      ERPCError::HandlerFailed(_) => "ERPC_HANDLER_FAILED",
+      ERPCError::Application(_) => "ERPC_APPLICATION",
+      ERPCError::Unauthorized(_) => "ERPC_UNAUTHORIZED",
+      ERPCError::Forbidden(_) => "ERPC_FORBIDDEN",
+      ERPCError::PayloadTooLarge(_) => "ERPC_PAYLOAD_TOO_LARGE",
+      ERPCError::Timeout => "ERPC_TIMEOUT",
+      ERPCError::Cancelled => "ERPC_CANCELLED",
+      ERPCError::SocketClosed => "ERPC_SOCKET_CLOSED",
+      ERPCError::IncompatibleVersion(_) => "ERPC_INCOMPATIBLE_VERSION",
+      ERPCError::Tls(_) => "ERPC_TLS",
+      ERPCError::Internal(_) => "ERPC_INTERNAL",
+    }
+  }
+
+  /**
+    The http status code this error is reported with
+  */
+  pub fn status_code(&self) -> StatusCode {
+    match self {
+      ERPCError::Transport(_) => StatusCode::BAD_GATEWAY,
+      ERPCError::Serialization(_) => StatusCode::BAD_REQUEST,
+      ERPCError::HandlerNotFound(_) => StatusCode::NOT_FOUND,
+      ERPCError::HandlerFailed(_) => StatusCode::INTERNAL_SERVER_ERROR,
+      ERPCError::Application(_) => StatusCode::UNPROCESSABLE_ENTITY,
+      ERPCError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+      ERPCError::Forbidden(_) => StatusCode::FORBIDDEN,
+      ERPCError::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+      ERPCError::Timeout => StatusCode::REQUEST_TIMEOUT,
+      ERPCError::Cancelled => StatusCode::REQUEST_TIMEOUT,
+      ERPCError::SocketClosed => StatusCode::SERVICE_UNAVAILABLE,
+      ERPCError::IncompatibleVersion(_) => StatusCode::UPGRADE_REQUIRED,
+      ERPCError::Tls(_) => StatusCode::INTERNAL_SERVER_ERROR,
+      ERPCError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+  }
+
+  /**
+    Reconstructs an error from the status code of an http response to a call of the handler with the given identifier
+  */
+  pub fn from_status(status: StatusCode, identifier: &str, message: String) -> Self {
+    match status {
+      StatusCode::BAD_REQUEST => ERPCError::Serialization(message),
+      StatusCode::UNAUTHORIZED => ERPCError::Unauthorized(message),
+      StatusCode::FORBIDDEN => ERPCError::Forbidden(message),
+      StatusCode::NOT_FOUND => ERPCError::HandlerNotFound(identifier.to_owned()),
+      StatusCode::PAYLOAD_TOO_LARGE => ERPCError::PayloadTooLarge(message),
+      StatusCode::UPGRADE_REQUIRED => ERPCError::IncompatibleVersion(message),
+      StatusCode::REQUEST_TIMEOUT | StatusCode::GATEWAY_TIMEOUT => ERPCError::Timeout,
+      StatusCode::INTERNAL_SERVER_ERROR => ERPCError::HandlerFailed(message),
+      StatusCode::UNPROCESSABLE_ENTITY => match serde_json::from_str(&message) {
+        Ok(v) => ERPCError::Application(v),
+        Err(_) => ERPCError::HandlerFailed(message),
+      },
+      _ => ERPCError::Transport(format!("Unexpected status {status}: {message}")),
+    }
+  }
+}
+
+impl Display for ERPCError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      ERPCError::Transport(err) => write!(f, "Transport error: {err}"),
+      ERPCError::Serialization(err) => write!(f, "Serialization error: {err}"),
+      ERPCError::HandlerNotFound(identifier) => {
+        write!(f, "No handler registered for {identifier}")
+      }
+      ERPCError::HandlerFailed(err) => write!(f, "Handler failed: {err}"),
+      ERPCError::Application(err) => write!(f, "{}: {}", err.code, err.message),
+      ERPCError::Unauthorized(err) => write!(f, "Unauthorized: {err}"),
+      ERPCError::Forbidden(err) => write!(f, "Forbidden: {err}"),
+      ERPCError::PayloadTooLarge(err) => write!(f, "Payload too large: {err}"),
+      ERPCError::Timeout => write!(f, "Request timed out"),
+      ERPCError::Cancelled => write!(f, "Request cancelled"),
+      ERPCError::SocketClosed => write!(f, "Socket closed"),
+      ERPCError::IncompatibleVersion(err) => write!(f, "Incompatible protocol version: {err}"),
+      ERPCError::Tls(err) => write!(f, "TLS error: {err}"),
+      ERPCError::Internal(err) => write!(f, "Internal error: {err}"),
+    }
+  }
+}
+
+impl std::error::Error for ERPCError {}
+
+impl From<serde_json::Error> for ERPCError {
+  fn from(err: serde_json::Error) -> Self {
+    ERPCError::Serialization(err.to_string())
+  }
+}
+
+impl From<reqwest::Error> for ERPCError {
+  fn from(err: reqwest::Error) -> Self {
+    if err.is_timeout() {
+      ERPCError::Timeout
+    } else {
+      ERPCError::Transport(err.to_string())
+    }
+  }
+}
@@ -5,6 +5,12 @@
 // https://github.com/actix/actix-web/blob/master/actix-web/src/handler.rs
 
 use futures_util::Future;
+use serde::{
+  de::{self, DeserializeOwned, Visitor},
+  forward_to_deserialize_any, Deserializer,
+};
+
+use super::{context::RequestContext, error::ERPCError};
 
 pub trait Handler<Args>: Send + Sync + Clone {
   type Output;
@@ -12,7 +18,92 @@ pub trait Handler<Args>: Send + Sync + Clone {
   fn call(&self, args: Args) -> Self::Future;
 }
 
+/**
+  A single argument of a handler.
+  Arguments are deserialized from the call parameters in order, a RequestContext argument receives the context of the call instead.
+*/
+pub trait FromRequest: Sized {
+  fn from_request(
+    context: &RequestContext,
+    parameters: &mut std::vec::IntoIter<serde_json::Value>,
+  ) -> Result<Self, ERPCError>;
+}
+
+impl<T: DeserializeOwned> FromRequest for T {
+  fn from_request(
+    _: &RequestContext,
+    parameters: &mut std::vec::IntoIter<serde_json::Value>,
+  ) -> Result<Self, ERPCError> {
+    match parameters.next() {
+      Some(v) => Ok(serde_json::from_value(v)?),
+      None => {
+        T::deserialize(MissingParameter).map_err(|err| ERPCError::Serialization(err.to_string()))
+      }
+    }
+  }
+}
+
+/**
+  Stands in for a parameter the caller left out. Only Option arguments can be deserialized from it, as None.
+*/
+struct MissingParameter;
+
+impl<'de> Deserializer<'de> for MissingParameter {
+  type Error = de::value::Error;
+
+  fn deserialize_any<V: Visitor<'de>>(self, _: V) -> Result<V::Value, Self::Error> {
+    Err(de::Error::custom(
+      "missing parameter, only optional parameters can be left out",
+    ))
+  }
+
+  fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+    visitor.visit_none()
+  }
+
+  forward_to_deserialize_any! {
+    bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+    bytes byte_buf unit unit_struct newtype_struct seq tuple
+    tuple_struct map struct enum identifier ignored_any
+  }
+}
+
+impl FromRequest for RequestContext {
+  fn from_request(
+    context: &RequestContext,
+    _: &mut std::vec::IntoIter<serde_json::Value>,
+  ) -> Result<Self, ERPCError> {
+    Ok(context.clone())
+  }
+}
+
+/**
+  The argument list of a handler, extracted from the parameters and context of a call
+*/
+pub trait HandlerArguments: Sized {
+  fn extract(
+    context: &RequestContext,
+    parameters: Vec<serde_json::Value>,
+  ) -> Result<Self, ERPCError>;
+}
+
 macro_rules! factory ({ $($param:ident)* } => {
+    impl<$($param: FromRequest,)*> HandlerArguments for ($($param,)*) {
+        #[allow(unused_mut, unused_variables)]
+        fn extract(context: &RequestContext, parameters: Vec<serde_json::Value>) -> Result<Self, ERPCError> {
+            let count = parameters.len();
+            let mut parameters = parameters.into_iter();
+            let arguments = ($($param::from_request(context, &mut parameters)?,)*);
+            if parameters.len() > 0 {
+                return Err(ERPCError::Serialization(format!(
+                    "Expected {} parameters, got {count}",
+                    count - parameters.len()
+                )));
+            }
+            Ok(arguments)
+        }
+    }
+
     impl<Func, Fut, Out, $($param,)*> Handler<($($param,)*)> for Func
     where
     Func: Fn($($param,)*) -> Fut + Send + Sync + Clone,
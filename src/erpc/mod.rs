@@ -1,12 +1,138 @@
+pub mod client;
+pub mod context;
+pub mod error;
 pub mod handler;
 pub mod protocol;
+pub mod pubsub;
 pub mod server;
 pub mod target;
 mod tests;
+pub mod tls;
+
+use std::{
+  collections::HashMap,
+  sync::{Arc, Mutex},
+};
+
+use tokio::sync::oneshot;
+
+/**
+  Calls made by the server via a socket, e.g. broadcasts, which still wait for their response
+*/
+pub type SocketCalls = Arc<Mutex<HashMap<String, oneshot::Sender<protocol::socket::Response>>>>;
 
 #[derive(Clone, Debug)]
 pub struct Socket {
+  /**
+    Unique id of the connection this socket represents
+  */
+  pub id: String,
   pub sender: flume::Sender<protocol::socket::SocketMessage>,
   pub reciever: flume::Receiver<protocol::socket::SocketMessage>,
   pub role: String,
+  /**
+    The protocol version negotiated with the peer during the handshake
+  */
+  pub version: u32,
+  /**
+    Lifecycle events of the connection
+  */
+  pub events: flume::Receiver<SocketEvent>,
+  /**
+    Requests closing the connection with a close code and reason
+  */
+  pub closer: flume::Sender<(u16, String)>,
+  /**
+    Open calls made via Socket::call. Responses to them are routed back by the server instead of being passed on to the reciever.
+  */
+  pub calls: SocketCalls,
+}
+
+/**
+  Removes a call from the open calls of a socket when it finishes, times out or is cancelled
+*/
+struct PendingCall {
+  id: String,
+  calls: SocketCalls,
+}
+
+impl Drop for PendingCall {
+  fn drop(&mut self) {
+    if let Ok(mut calls) = self.calls.lock() {
+      calls.remove(&self.id);
+    }
+  }
+}
+
+impl Socket {
+  /**
+    Closes the connection with the given close code and reason
+  */
+  pub fn close(&self, code: u16, reason: String) -> Result<(), error::ERPCError> {
+    self
+      .closer
+      .send((code, reason))
+      .map_err(|_| error::ERPCError::SocketClosed)
+  }
+
+  /**
+    Calls a handler of the client and waits for the response, independently of a target listening on this socket
+  */
+  pub async fn call(
+    &self,
+    request: protocol::Request,
+  ) -> Result<serde_json::Value, error::ERPCError> {
+    let id = nanoid::nanoid!();
+    let (sender, reciever) = oneshot::channel();
+    self
+      .calls
+      .lock()
+      .map_err(|err| error::ERPCError::Internal(format!("Could not access calls: {err}")))?
+      .insert(id.clone(), sender);
+    let _pending = PendingCall {
+      id: id.clone(),
+      calls: self.calls.clone(),
+    };
+
+    self
+      .sender
+      .send(protocol::socket::SocketMessage::Request(
+        protocol::socket::Request { id, request },
+      ))
+      .map_err(|_| error::ERPCError::SocketClosed)?;
+
+    let response = reciever.await.map_err(|_| error::ERPCError::SocketClosed)?;
+    let response = response.body.map_err(error::ERPCError::HandlerFailed)?;
+    match response.error {
+      Some(err) => Err(error::ERPCError::Application(err)),
+      None => Ok(response.body),
+    }
+  }
+
+  /**
+    Sends a notification to the client
+  */
+  pub fn notify(&self, request: protocol::Request) -> Result<(), error::ERPCError> {
+    self
+      .sender
+      .send(protocol::socket::SocketMessage::Notification(
+        protocol::socket::Notification { request },
+      ))
+      .map_err(|_| error::ERPCError::SocketClosed)
+  }
+}
+
+/**
+  Lifecycle events of a socket connection
+*/
+#[derive(Clone, Debug)]
+pub enum SocketEvent {
+  /**
+    Reading from or writing to the socket failed
+  */
+  Error(String),
+  /**
+    The connection has been closed. The code is missing when the connection was closed without a close frame.
+  */
+  Close { code: Option<u16>, reason: String },
 }
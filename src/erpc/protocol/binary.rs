@@ -0,0 +1,186 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde::{de::Error, Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::{Map, Value};
+
+use crate::erpc::error::ERPCError;
+
+/**
+  The key marking objects which stand for a value json can not represent, e.g. `{"$erpc": "binary", "data": "aGVsbG8="}`.
+  Only objects with exactly the keys TAG_KEY and DATA_KEY and a known tag are read as such, any other object is left as is.
+*/
+pub const TAG_KEY: &str = "$erpc";
+
+/**
+  The tag of binary values
+*/
+pub const BINARY_TAG: &str = "binary";
+
+/**
+  The key holding the payload of a tagged value.
+  For binary values it is the base64 encoded data in json payloads and the index of the attachment in binary websocket frames.
+*/
+pub const DATA_KEY: &str = "data";
+
+/**
+  Binary data, e.g. a Buffer or Uint8Array in JS.
+  Handlers can take and return it like any other value.
+*/
+#[allow(dead_code)]
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Binary(pub Vec<u8>);
+
+impl Serialize for Binary {
+  fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    encode(&self.0).serialize(serializer)
+  }
+}
+
+impl<'de> Deserialize<'de> for Binary {
+  fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+    let value = Value::deserialize(deserializer)?;
+    match decode(&value) {
+      Some(v) => Ok(Binary(v)),
+      None => Err(D::Error::custom(format!(
+        "expected binary data in the form {{\"{TAG_KEY}\": \"{BINARY_TAG}\", \"{DATA_KEY}\": \"<base64>\"}}"
+      ))),
+    }
+  }
+}
+
+/**
+  Wraps binary data into its json representation
+*/
+pub fn encode(data: &[u8]) -> Value {
+  tagged(Value::String(STANDARD.encode(data)))
+}
+
+/**
+  Reads binary data from its json representation. Returns None for any other value.
+*/
+pub fn decode(value: &Value) -> Option<Vec<u8>> {
+  match tagged_data(value) {
+    Some(Value::String(v)) => STANDARD.decode(v).ok(),
+    _ => None,
+  }
+}
+
+fn tagged(data: Value) -> Value {
+  let mut map = Map::new();
+  map.insert(TAG_KEY.to_owned(), Value::from(BINARY_TAG));
+  map.insert(DATA_KEY.to_owned(), data);
+  Value::Object(map)
+}
+
+/**
+  The data of a value tagged as binary, None for any other value
+*/
+fn tagged_data(value: &Value) -> Option<&Value> {
+  match value {
+    Value::Object(map) if map.len() == 2 && map.get(TAG_KEY)?.as_str()? == BINARY_TAG => {
+      map.get(DATA_KEY)
+    }
+    _ => None,
+  }
+}
+
+/**
+  Encodes a message into a binary websocket frame when it contains binary values, so they are sent without the base64 overhead.
+  The frame consists of the length of the json header as big endian u32, the json header in which binary values are
+  replaced by their attachment index, followed by each attachment prefixed by its length as big endian u32.
+  Returns None when the message contains no binary values and can be sent as text.
+*/
+pub fn to_frame(message: &Value) -> Option<Vec<u8>> {
+  let mut attachments = Vec::new();
+  let header = extract_attachments(message, &mut attachments);
+  if attachments.is_empty() {
+    return None;
+  }
+
+  let header = serde_json::to_vec(&header).ok()?;
+  let mut frame =
+    Vec::with_capacity(4 + header.len() + attachments.iter().map(|v| v.len() + 4).sum::<usize>());
+  frame.extend_from_slice(&(header.len() as u32).to_be_bytes());
+  frame.extend_from_slice(&header);
+  for attachment in attachments {
+    frame.extend_from_slice(&(attachment.len() as u32).to_be_bytes());
+    frame.extend_from_slice(&attachment);
+  }
+  Some(frame)
+}
+
+/**
+  Decodes a binary websocket frame created by to_frame back into a message with json binary values
+*/
+pub fn from_frame(frame: &[u8]) -> Result<Value, ERPCError> {
+  let mut rest = frame;
+  let header: Value = serde_json::from_slice(next_part(&mut rest)?)?;
+  let mut attachments = Vec::new();
+  while !rest.is_empty() {
+    attachments.push(next_part(&mut rest)?);
+  }
+
+  insert_attachments(header, &attachments)
+}
+
+/**
+  Splits off the next length prefixed part of a binary frame
+*/
+fn next_part<'a>(rest: &mut &'a [u8]) -> Result<&'a [u8], ERPCError> {
+  let invalid = || ERPCError::Serialization("Invalid binary frame".to_string());
+  if rest.len() < 4 {
+    return Err(invalid());
+  }
+  let (length, data) = rest.split_at(4);
+  let length = u32::from_be_bytes([length[0], length[1], length[2], length[3]]) as usize;
+  if data.len() < length {
+    return Err(invalid());
+  }
+  let (part, remaining) = data.split_at(length);
+  *rest = remaining;
+  Ok(part)
+}
+
+fn extract_attachments(value: &Value, attachments: &mut Vec<Vec<u8>>) -> Value {
+  if let Some(data) = decode(value) {
+    attachments.push(data);
+    return tagged(Value::from(attachments.len() - 1));
+  }
+
+  match value {
+    Value::Array(v) => Value::Array(
+      v.iter()
+        .map(|v| extract_attachments(v, attachments))
+        .collect(),
+    ),
+    Value::Object(v) => Value::Object(
+      v.iter()
+        .map(|(k, v)| (k.clone(), extract_attachments(v, attachments)))
+        .collect(),
+    ),
+    v => v.clone(),
+  }
+}
+
+fn insert_attachments(value: Value, attachments: &[&[u8]]) -> Result<Value, ERPCError> {
+  if let Some(index) = tagged_data(&value) {
+    let attachment = index
+      .as_u64()
+      .and_then(|v| attachments.get(v as usize))
+      .ok_or_else(|| ERPCError::Serialization("Invalid attachment index".to_string()))?;
+    return Ok(encode(attachment));
+  }
+
+  match value {
+    Value::Array(v) => Ok(Value::Array(
+      v.into_iter()
+        .map(|v| insert_attachments(v, attachments))
+        .collect::<Result<_, _>>()?,
+    )),
+    Value::Object(v) => Ok(Value::Object(
+      v.into_iter()
+        .map(|(k, v)| Ok((k, insert_attachments(v, attachments)?)))
+        .collect::<Result<_, ERPCError>>()?,
+    )),
+    v => Ok(v),
+  }
+}
@@ -0,0 +1,236 @@
+use std::fmt;
+
+use serde::{
+  de::{DeserializeOwned, MapAccess, SeqAccess, Visitor},
+  ser::{SerializeMap, SerializeSeq},
+  Deserialize, Deserializer, Serialize, Serializer,
+};
+use serde_json::{Map, Value};
+
+use super::binary;
+use crate::erpc::error::ERPCError;
+
+/**
+  The wire encoding of requests and responses.
+  Binary values are sent in their json representation (see binary) with json and as bin with MessagePack.
+*/
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum Encoding {
+  #[default]
+  Json,
+  MessagePack,
+}
+
+impl Encoding {
+  /**
+    The name of the encoding as used in the websocket subprotocol and the encoding query parameter
+  */
+  pub fn name(&self) -> &'static str {
+    match self {
+      Encoding::Json => "json",
+      Encoding::MessagePack => "msgpack",
+    }
+  }
+
+  pub fn from_name(name: &str) -> Option<Self> {
+    match name.trim() {
+      "json" => Some(Encoding::Json),
+      "msgpack" => Some(Encoding::MessagePack),
+      _ => None,
+    }
+  }
+
+  pub fn content_type(&self) -> &'static str {
+    match self {
+      Encoding::Json => "application/json",
+      Encoding::MessagePack => "application/msgpack",
+    }
+  }
+
+  /**
+    Reads the encoding from a Content-Type header, ignoring parameters like charset
+  */
+  pub fn from_content_type(content_type: &str) -> Option<Self> {
+    match content_type.split(';').next()?.trim() {
+      "application/json" => Some(Encoding::Json),
+      "application/msgpack" | "application/x-msgpack" | "application/vnd.msgpack" => {
+        Some(Encoding::MessagePack)
+      }
+      _ => None,
+    }
+  }
+
+  /**
+    Picks the first supported encoding from an Accept header
+  */
+  pub fn from_accept(accept: &str) -> Option<Self> {
+    accept.split(',').find_map(Self::from_content_type)
+  }
+
+  pub fn serialize<T: Serialize>(&self, value: &T) -> Result<Vec<u8>, ERPCError> {
+    match self {
+      Encoding::Json => Ok(serde_json::to_vec(value)?),
+      Encoding::MessagePack => {
+        rmp_serde::to_vec_named(&MessagePackValue(&serde_json::to_value(value)?))
+          .map_err(|err| ERPCError::Serialization(err.to_string()))
+      }
+    }
+  }
+
+  pub fn deserialize<T: DeserializeOwned>(&self, data: &[u8]) -> Result<T, ERPCError> {
+    match self {
+      Encoding::Json => Ok(serde_json::from_slice(data)?),
+      Encoding::MessagePack => {
+        let value = rmp_serde::from_slice::<OwnedMessagePackValue>(data)
+          .map_err(|err| ERPCError::Serialization(err.to_string()))?;
+        Ok(serde_json::from_value(value.0)?)
+      }
+    }
+  }
+}
+
+/**
+  Serializes json with binary values as MessagePack bin instead of their json representation
+*/
+struct MessagePackValue<'a>(&'a Value);
+
+impl Serialize for MessagePackValue<'_> {
+  fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    if let Some(data) = binary::decode(self.0) {
+      return serializer.serialize_bytes(&data);
+    }
+
+    match self.0 {
+      Value::Array(v) => {
+        let mut seq = serializer.serialize_seq(Some(v.len()))?;
+        for element in v {
+          seq.serialize_element(&MessagePackValue(element))?;
+        }
+        seq.end()
+      }
+      Value::Object(v) => {
+        let mut map = serializer.serialize_map(Some(v.len()))?;
+        for (key, value) in v {
+          map.serialize_entry(key, &MessagePackValue(value))?;
+        }
+        map.end()
+      }
+      v => v.serialize(serializer),
+    }
+  }
+}
+
+/**
+  Deserializes MessagePack into json, turning bin into binary values
+*/
+struct OwnedMessagePackValue(Value);
+
+impl<'de> Deserialize<'de> for OwnedMessagePackValue {
+  fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+    deserializer.deserialize_any(MessagePackVisitor)
+  }
+}
+
+struct MessagePackVisitor;
+
+impl<'de> Visitor<'de> for MessagePackVisitor {
+  type Value = OwnedMessagePackValue;
+
+  fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.write_str("a MessagePack value")
+  }
+
+  fn visit_bool<E>(self, v: bool) -> Result<Self::Value, E> {
+    Ok(OwnedMessagePackValue(Value::Bool(v)))
+  }
+
+  fn visit_i64<E>(self, v: i64) -> Result<Self::Value, E> {
+    Ok(OwnedMessagePackValue(Value::from(v)))
+  }
+
+  fn visit_u64<E>(self, v: u64) -> Result<Self::Value, E> {
+    Ok(OwnedMessagePackValue(Value::from(v)))
+  }
+
+  fn visit_f64<E>(self, v: f64) -> Result<Self::Value, E> {
+    Ok(OwnedMessagePackValue(Value::from(v)))
+  }
+
+  fn visit_str<E>(self, v: &str) -> Result<Self::Value, E> {
+    Ok(OwnedMessagePackValue(Value::from(v)))
+  }
+
+  fn visit_string<E>(self, v: String) -> Result<Self::Value, E> {
+    Ok(OwnedMessagePackValue(Value::String(v)))
+  }
+
+  fn visit_bytes<E>(self, v: &[u8]) -> Result<Self::Value, E> {
+    Ok(OwnedMessagePackValue(binary::encode(v)))
+  }
+
+  fn visit_unit<E>(self) -> Result<Self::Value, E> {
+    Ok(OwnedMessagePackValue(Value::Null))
+  }
+
+  fn visit_none<E>(self) -> Result<Self::Value, E> {
+    Ok(OwnedMessagePackValue(Value::Null))
+  }
+
+  fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+    OwnedMessagePackValue::deserialize(deserializer)
+  }
+
+  fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+    let mut array = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+    while let Some(OwnedMessagePackValue(v)) = seq.next_element()? {
+      array.push(v);
+    }
+    Ok(OwnedMessagePackValue(Value::Array(array)))
+  }
+
+  fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+    let mut object = Map::new();
+    while let Some((key, OwnedMessagePackValue(v))) = map.next_entry::<String, _>()? {
+      object.insert(key, v);
+    }
+    Ok(OwnedMessagePackValue(Value::Object(object)))
+  }
+}
+
+/**
+  The formats streamed responses can be sent in over http, negotiated via the Accept header.
+  Each event or line carries a stream message as used over websockets.
+*/
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StreamFormat {
+  /**
+    Server-sent events, named after the type of the message
+  */
+  EventStream,
+  /**
+    One json message per line
+  */
+  Ndjson,
+}
+
+impl StreamFormat {
+  pub fn content_type(&self) -> &'static str {
+    match self {
+      StreamFormat::EventStream => "text/event-stream",
+      StreamFormat::Ndjson => "application/x-ndjson",
+    }
+  }
+
+  /**
+    Picks the first stream format from an Accept header
+  */
+  pub fn from_accept(accept: &str) -> Option<Self> {
+    accept
+      .split(',')
+      .find_map(|v| match v.split(';').next()?.trim() {
+        "text/event-stream" => Some(StreamFormat::EventStream),
+        "application/x-ndjson" => Some(StreamFormat::Ndjson),
+        _ => None,
+      })
+  }
+}
@@ -1,21 +1,209 @@
+pub mod binary;
+pub mod encoding;
 pub mod socket;
 
 use serde::{Deserialize, Serialize};
 
+use crate::erpc::error::ERPCError;
+
+/**
+   The version of the protocol spoken by this implementation, sent along with every envelope
+*/
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/**
+   The oldest protocol version still understood. Peers not sending a version are treated as speaking this one.
+*/
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
+/**
+   The header peers send the newest protocol version they support in, and servers answer with the negotiated one
+*/
+pub const VERSION_HEADER: &str = "x-erpc-version";
+
+/**
+   Picks the version to talk to a peer supporting up to the requested version, the newest one both sides support
+*/
+pub fn negotiate_version(requested: Option<&str>) -> Result<u32, ERPCError> {
+  let requested = match requested {
+    Some(v) => v
+      .trim()
+      .parse::<u32>()
+      .map_err(|_| ERPCError::IncompatibleVersion(format!("Invalid protocol version {v}")))?,
+    None => return Ok(MIN_PROTOCOL_VERSION),
+  };
+
+  if requested < MIN_PROTOCOL_VERSION {
+    return Err(ERPCError::IncompatibleVersion(format!(
+      "Protocol version {requested} is not supported anymore, at least {MIN_PROTOCOL_VERSION} is required"
+    )));
+  }
+  Ok(requested.min(PROTOCOL_VERSION))
+}
+
 /**
    In incoming erpc request.
    When no parameters are sent, the vec is empty
 */
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Request {
   pub identifier: String,
   pub parameters: Vec<serde_json::Value>,
 }
 
+/**
+   The body of a call via http, wrapping the request with the information needed to validate and trace it.
+   Older clients send the bare parameters instead, see Envelope::from_body.
+   Unknown fields are ignored, so newer clients can add fields without breaking older servers.
+*/
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Envelope {
+  /**
+     The protocol version the request has been created with
+  */
+  pub version: u32,
+  /**
+     Identifies the request, e.g. in logs and the request context. Generated by the server when missing.
+  */
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub id: Option<String>,
+  pub identifier: String,
+  #[serde(default)]
+  pub parameters: Vec<serde_json::Value>,
+}
+
+impl Envelope {
+  pub fn new(request: Request, id: String) -> Self {
+    Envelope {
+      version: PROTOCOL_VERSION,
+      id: Some(id),
+      identifier: request.identifier,
+      parameters: request.parameters,
+    }
+  }
+
+  /**
+     Reads the body of a call of the handler with the given identifier, using the version negotiated via header.
+     Arrays are taken as the bare parameters of unversioned requests, i.e. of the oldest protocol version.
+  */
+  pub fn from_body(
+    body: serde_json::Value,
+    identifier: &str,
+    version: u32,
+  ) -> Result<Self, ERPCError> {
+    let envelope = match body {
+      serde_json::Value::Array(parameters) => {
+        return Ok(Envelope {
+          version: MIN_PROTOCOL_VERSION,
+          id: None,
+          identifier: identifier.to_owned(),
+          parameters,
+        })
+      }
+      body @ serde_json::Value::Object(_) => Self::from_value(body, version)?,
+      _ => {
+        return Err(ERPCError::Serialization(
+          "Expected a request envelope or an array of parameters".to_string(),
+        ))
+      }
+    };
+
+    if envelope.identifier != identifier {
+      return Err(ERPCError::Serialization(format!(
+        "The request is addressed to {}, but has been sent to {identifier}",
+        envelope.identifier
+      )));
+    }
+    Ok(envelope)
+  }
+
+  /**
+     Reads a request envelope, e.g. an item of a batch, using the version negotiated via header.
+     Envelopes created with a newer version than the negotiated one are read as the negotiated version.
+  */
+  pub fn from_value(value: serde_json::Value, version: u32) -> Result<Self, ERPCError> {
+    let mut envelope = serde_json::from_value::<Envelope>(value)?;
+    if envelope.version < MIN_PROTOCOL_VERSION {
+      return Err(ERPCError::IncompatibleVersion(format!(
+        "Protocol version {} is not supported anymore, at least {MIN_PROTOCOL_VERSION} is required",
+        envelope.version
+      )));
+    }
+    envelope.version = envelope.version.min(version);
+    if let Some(id) = &envelope.id {
+      if id.is_empty() || id.len() > 128 {
+        return Err(ERPCError::Serialization(
+          "The request id must have between 1 and 128 characters".to_string(),
+        ));
+      }
+    }
+    Ok(envelope)
+  }
+}
+
 /**
    An outgoing erpc response
 */
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Response {
   pub body: serde_json::Value,
+  /**
+     A user defined error thrown by the handler. When set, the body carries no value.
+  */
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub error: Option<ApplicationError>,
+}
+
+/**
+   A user defined error (e.g. wrongPassword) thrown by a handler.
+   In contrast to internal errors these are meant to be handled by the caller.
+*/
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ApplicationError {
+  pub code: String,
+  pub message: String,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub data: Option<serde_json::Value>,
+}
+
+/**
+   The outcome of a single call of a batch request, answered like the call would have been via handlers/{identifier}
+*/
+#[derive(Serialize, Deserialize, Debug)]
+pub struct BatchResult {
+  /**
+     The http status code the call would have been answered with
+  */
+  pub status: u16,
+  /**
+     The returned value, an application error or an error message, depending on the status
+  */
+  pub body: serde_json::Value,
+}
+
+/**
+   Optional information about a handler, supplied when registering it
+*/
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct HandlerMetadata {
+  /**
+     What the handler does, in human readable form
+  */
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub description: Option<String>,
+  /**
+     A schema of the parameters the handler expects, e.g. a JSON schema
+  */
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub parameters: Option<serde_json::Value>,
+}
+
+/**
+   A registered handler as listed by the handlers endpoint
+*/
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct HandlerDescription {
+  pub identifier: String,
+  #[serde(flatten)]
+  pub metadata: HandlerMetadata,
 }
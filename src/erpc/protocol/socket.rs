@@ -1,13 +1,125 @@
-use serde::{Serialize, Deserialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use super::{binary, encoding::Encoding};
+use crate::erpc::error::ERPCError;
 
 /**
-   A socket message
+   The protocol version from which on socket messages are decoded by their type.
+   Peers speaking an older version send untagged messages, see LegacySocketMessage.
 */
-#[derive(Serialize, Deserialize)]
-#[serde(untagged)]
+pub const TAGGED_VERSION: u32 = 2;
+
+/**
+   A socket message, tagged with its kind in the type field, e.g. `{"type": "Request", ...}`
+*/
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type")]
 pub enum SocketMessage {
   Request(Request),
+  Notification(Notification),
+  Response(Response),
+  StreamItem(StreamItem),
+  StreamError(StreamError),
+  StreamEnd(StreamEnd),
+  Subscribe(Subscribe),
+  Unsubscribe(Unsubscribe),
+  Ping(Ping),
+  Pong(Pong),
+  Error(ProtocolError),
+}
+
+/**
+   A socket message of peers not sending the type, recognized by trying each kind in order
+*/
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum LegacySocketMessage {
+  Request(Request),
+  Notification(Notification),
   Response(Response),
+  StreamItem(StreamItem),
+  StreamError(StreamError),
+  StreamEnd(StreamEnd),
+  Subscribe(Subscribe),
+  Unsubscribe(Unsubscribe),
+}
+
+impl From<LegacySocketMessage> for SocketMessage {
+  fn from(message: LegacySocketMessage) -> Self {
+    match message {
+      LegacySocketMessage::Request(v) => SocketMessage::Request(v),
+      LegacySocketMessage::Notification(v) => SocketMessage::Notification(v),
+      LegacySocketMessage::Response(v) => SocketMessage::Response(v),
+      LegacySocketMessage::StreamItem(v) => SocketMessage::StreamItem(v),
+      LegacySocketMessage::StreamError(v) => SocketMessage::StreamError(v),
+      LegacySocketMessage::StreamEnd(v) => SocketMessage::StreamEnd(v),
+      LegacySocketMessage::Subscribe(v) => SocketMessage::Subscribe(v),
+      LegacySocketMessage::Unsubscribe(v) => SocketMessage::Unsubscribe(v),
+    }
+  }
+}
+
+/**
+   An encoded socket message, ready to be sent as websocket frame
+*/
+pub enum Frame {
+  Text(String),
+  Binary(Vec<u8>),
+}
+
+impl SocketMessage {
+  /**
+     Parses a websocket frame. Text frames are always json.
+     With json encoding binary frames carry binary values as attachments, see binary::to_frame.
+     Peers speaking a version before TAGGED_VERSION are decoded as legacy untagged messages.
+  */
+  pub fn parse(
+    data: &[u8],
+    is_binary: bool,
+    encoding: Encoding,
+    version: u32,
+  ) -> Result<Self, ERPCError> {
+    if version < TAGGED_VERSION {
+      return Ok(Self::decode::<LegacySocketMessage>(data, is_binary, encoding)?.into());
+    }
+    Self::decode(data, is_binary, encoding)
+  }
+
+  fn decode<T: DeserializeOwned>(
+    data: &[u8],
+    is_binary: bool,
+    encoding: Encoding,
+  ) -> Result<T, ERPCError> {
+    match (is_binary, encoding) {
+      (false, _) => Ok(serde_json::from_slice(data)?),
+      (true, Encoding::Json) => Ok(serde_json::from_value(binary::from_frame(data)?)?),
+      (true, encoding) => encoding.deserialize(data),
+    }
+  }
+
+  /**
+     Reads the id of a frame which could not be parsed, so the error can be reported for the right request
+  */
+  pub fn malformed_id(data: &[u8], is_binary: bool, encoding: Encoding) -> Option<String> {
+    let value: serde_json::Value = Self::decode(data, is_binary, encoding).ok()?;
+    value.get("id")?.as_str().map(|v| v.to_owned())
+  }
+
+  /**
+     Encodes the message for the given encoding.
+     Json messages are sent as binary frame when they contain binary values, as text frame otherwise
+  */
+  pub fn encode(&self, encoding: Encoding) -> Result<Frame, ERPCError> {
+    if encoding != Encoding::Json {
+      return Ok(Frame::Binary(encoding.serialize(self)?));
+    }
+
+    let value = serde_json::to_value(self)?;
+    match binary::to_frame(&value) {
+      Some(v) => Ok(Frame::Binary(v)),
+      None => Ok(Frame::Text(serde_json::to_string(&value)?)),
+    }
+  }
 }
 
 /**
@@ -25,21 +137,127 @@ pub struct Request {
   pub request: super::Request,
 }
 
+/**
+    A request via websockets which is handled without sending a response
+*/
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Notification {
+  pub request: super::Request,
+}
+
 /*
     A response to a websocket request
 */
 #[derive(Serialize, Deserialize, Debug)]
-#[serde(tag = "type")]
 pub struct Response {
   /**
       The id of the request this response refers to
   */
   pub id: String,
   /**
-      A result containing the response or an error string if there has been an internal error while processing the request.
-      The error does not indicate a user defined error (e.g. wrongPassword) but a internal error (e.g. could not parse body).
-      We need this error type because when requesting via sockets there is no way of indicating an error via the http status code.
-      The user should not be able to set the error value, this is reserved to indicate an actual internal error.
-   */
+     A result containing the response or an error string if there has been an internal error while processing the request.
+     The error does not indicate a user defined error (e.g. wrongPassword) but a internal error (e.g. could not parse body).
+     We need this error type because when requesting via sockets there is no way of indicating an error via the http status code.
+     The user should not be able to set the error value, this is reserved to indicate an actual internal error.
+  */
   pub body: Result<super::Response, String>,
 }
+
+/**
+    An item of a streamed response to a websocket request.
+    A stream consists of any number of items followed by either a StreamEnd or a StreamError.
+*/
+#[derive(Serialize, Deserialize, Debug)]
+pub struct StreamItem {
+  /**
+      The id of the request this item refers to
+  */
+  pub id: String,
+  pub item: serde_json::Value,
+}
+
+/**
+    Ends a streamed response after its last item
+*/
+#[derive(Serialize, Deserialize, Debug)]
+pub struct StreamEnd {
+  /**
+      The id of the request the stream belongs to
+  */
+  pub id: String,
+  /**
+      Always true. Distinguishes the message from other messages carrying an id in the legacy untagged format.
+  */
+  pub done: bool,
+}
+
+/**
+    Ends a streamed response with an error
+*/
+#[derive(Serialize, Deserialize, Debug)]
+pub struct StreamError {
+  /**
+      The id of the request the stream belongs to
+  */
+  pub id: String,
+  /**
+      Like the body of a response, a user defined error thrown while streaming or an internal error string
+  */
+  pub error: Result<super::ApplicationError, String>,
+}
+
+/**
+    Subscribes the socket to a topic, so it recieves what the server publishes to it
+*/
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Subscribe {
+  /**
+      The topic to subscribe to
+  */
+  pub subscribe: String,
+}
+
+/**
+    Ends the subscription of the socket to a topic
+*/
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Unsubscribe {
+  /**
+      The topic to unsubscribe from
+  */
+  pub unsubscribe: String,
+}
+
+/**
+    Checks whether the connection is alive, answered with a Pong carrying the same id
+*/
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Ping {
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub id: Option<String>,
+}
+
+/**
+    The answer to a Ping
+*/
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Pong {
+  /**
+      The id of the Ping this answers
+  */
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub id: Option<String>,
+}
+
+/**
+    Reports a frame which could not be handled, e.g. because it is malformed. The connection stays open.
+*/
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ProtocolError {
+  /**
+      The id of the message the error refers to, if it could be read
+  */
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub id: Option<String>,
+  pub message: String,
+}
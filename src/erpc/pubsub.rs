@@ -0,0 +1,81 @@
+use std::{
+  collections::HashMap,
+  sync::{Arc, RwLock},
+};
+
+use super::Socket;
+
+/**
+  Keeps track of which sockets subscribed to which topic.
+  Servers sharing an instance can publish to the sockets of each other.
+*/
+pub trait PubSub: Send + Sync {
+  fn subscribe(&self, topic: &str, socket: Socket);
+  fn unsubscribe(&self, topic: &str, socket_id: &str);
+  /**
+    Removes the socket from all topics, e.g. once it disconnected
+  */
+  fn unsubscribe_all(&self, socket_id: &str);
+  fn subscribers(&self, topic: &str) -> Vec<Socket>;
+}
+
+/**
+  A pub/sub within the current process, used by servers by default
+*/
+#[derive(Clone, Default)]
+pub struct LocalPubSub {
+  /**
+    The subscribed sockets by topic and socket id
+  */
+  topics: Arc<RwLock<HashMap<String, HashMap<String, Socket>>>>,
+}
+
+impl PubSub for LocalPubSub {
+  fn subscribe(&self, topic: &str, socket: Socket) {
+    match self.topics.write() {
+      Ok(mut v) => {
+        v.entry(topic.to_owned())
+          .or_default()
+          .insert(socket.id.clone(), socket);
+      }
+      Err(err) => eprintln!("Could not access topics: {err}"),
+    }
+  }
+
+  fn unsubscribe(&self, topic: &str, socket_id: &str) {
+    match self.topics.write() {
+      Ok(mut v) => {
+        if let Some(sockets) = v.get_mut(topic) {
+          sockets.remove(socket_id);
+          if sockets.is_empty() {
+            v.remove(topic);
+          }
+        }
+      }
+      Err(err) => eprintln!("Could not access topics: {err}"),
+    }
+  }
+
+  fn unsubscribe_all(&self, socket_id: &str) {
+    match self.topics.write() {
+      Ok(mut v) => v.retain(|_, sockets| {
+        sockets.remove(socket_id);
+        !sockets.is_empty()
+      }),
+      Err(err) => eprintln!("Could not access topics: {err}"),
+    }
+  }
+
+  fn subscribers(&self, topic: &str) -> Vec<Socket> {
+    match self.topics.read() {
+      Ok(v) => v
+        .get(topic)
+        .map(|v| v.values().cloned().collect())
+        .unwrap_or_default(),
+      Err(err) => {
+        eprintln!("Could not access topics: {err}");
+        Vec::new()
+      }
+    }
+  }
+}
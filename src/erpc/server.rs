@@ -1,35 +1,126 @@
-use super::{protocol::socket::SocketMessage, Socket};
-use futures_util::{Future, SinkExt, StreamExt};
+use super::{
+  context::{RequestContext, RequestMetadata},
+  error::ERPCError,
+  handler::HandlerArguments,
+  protocol::{
+    self,
+    encoding::{Encoding, StreamFormat},
+    socket::{
+      Frame, Pong, ProtocolError, SocketMessage, StreamEnd, StreamError, StreamItem, TAGGED_VERSION,
+    },
+    HandlerDescription, HandlerMetadata,
+  },
+  pubsub::{LocalPubSub, PubSub},
+  tls::CertificateResolver,
+  Socket, SocketEvent,
+};
+use futures_util::{Future, FutureExt, SinkExt, Stream, StreamExt};
+use hyper::{
+  server::{accept, conn::AddrStream},
+  service::{make_service_fn, service_fn, Service},
+};
 use reqwest::{Method, StatusCode};
 use serde::{de::DeserializeOwned, Serialize};
 use std::{
   collections::HashMap,
+  convert::Infallible,
+  net::{SocketAddr, TcpListener},
   pin::Pin,
   sync::{Arc, RwLock},
+  time::Duration,
+};
+use tokio::{net::TcpStream, sync::oneshot};
+use tokio_rustls::{server::TlsStream, TlsAcceptor};
+use warp::{
+  http::{HeaderName, HeaderValue},
+  path::Peek,
+  Buf, Filter, Reply,
 };
-use tokio::sync::oneshot;
-use warp::{path::Peek, Filter, Reply};
 
 //TODO: include in docs that credentials are sent by default
-//TODO: ensure conversion to a protocol struct on each recieved call
 
 type Handler = Box<
   dyn Fn(
       Vec<serde_json::Value>,
-    ) -> Pin<Box<dyn Future<Output = Result<serde_json::Value, String>> + Send + Sync>>
+      RequestContext,
+    ) -> Pin<Box<dyn Future<Output = Result<HandlerResponse, ERPCError>> + Send + Sync>>
+    + Send
+    + Sync,
+>;
+
+/**
+  The items of a streamed response. An error ends the stream.
+*/
+pub type ResponseStream = Pin<Box<dyn Stream<Item = Result<serde_json::Value, ERPCError>> + Send>>;
+
+/**
+  What a handler responds with, either a single value or a stream of values
+*/
+pub enum HandlerResponse {
+  Value(serde_json::Value),
+  Stream(ResponseStream),
+}
+
+/**
+  Authenticates incoming calls and socket connections.
+  Resolves to an auth context handlers can read or rejects the call, usually with ERPCError::Unauthorized or ERPCError::Forbidden
+*/
+pub type Guard = Box<
+  dyn Fn(
+      RequestMetadata,
+    ) -> Pin<Box<dyn Future<Output = Result<Option<serde_json::Value>, ERPCError>> + Send>>
     + Send
     + Sync,
 >;
 
+/**
+  The address of the client, attached to each request as extension
+*/
+#[derive(Clone, Copy)]
+struct RemoteAddress(SocketAddr);
+
+/**
+  The default maximum size of request bodies and websocket messages in bytes
+*/
+pub const DEFAULT_MAX_BODY_SIZE: u64 = 1024 * 1024;
+
+/**
+  How long clients have to complete the TLS handshake before their connection is dropped
+*/
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/**
+  How long to wait before accepting connections again after accepting failed, e.g. because there are no file descriptors left
+*/
+const ACCEPT_ERROR_DELAY: Duration = Duration::from_millis(100);
+
 type SocketChannel = (flume::Sender<Socket>, flume::Receiver<Socket>);
 
+/**
+  The live sockets grouped by role and socket id
+*/
+type SocketRegistry = Arc<RwLock<HashMap<String, HashMap<String, Socket>>>>;
+
+/**
+  A request handler and the metadata it is listed with by the handlers endpoint
+*/
+struct RegisteredHandler {
+  handler: Arc<Handler>,
+  metadata: HandlerMetadata,
+}
+
+/**
+  The registered request handlers by identifier
+*/
+type Handlers = Arc<tokio::sync::RwLock<HashMap<String, RegisteredHandler>>>;
+
 //TODO: check where rwlock/mutex is necessary
 #[derive(Clone)]
 pub struct ERPCServer {
   /**
-    The port the server runs on
+    The addresses the server listens on
   */
-  port: u16,
+  addresses: Vec<SocketAddr>,
   /**
     Whether the server should accept websocket connections
   */
@@ -41,7 +132,11 @@ pub struct ERPCServer {
   /**
     Request handlers for incoming requests to this server
   */
-  handlers: Arc<tokio::sync::RwLock<HashMap<String, Handler>>>,
+  handlers: Handlers,
+  /**
+    Whether the registered handlers are listed via GET handlers
+  */
+  introspection: bool,
   /**
     Shutdown signal to exit the webserver gracefully
   */
@@ -50,79 +145,243 @@ pub struct ERPCServer {
     Channel to broadcast connected sockets
   */
   socket_channel: SocketChannel,
+  /**
+    The live sockets by role, used for broadcasting
+  */
+  sockets: SocketRegistry,
+  /**
+    The subscriptions of sockets to topics
+  */
+  pub_sub: Arc<dyn PubSub>,
+  /**
+    The certificate to serve when TLS is enabled
+  */
+  tls: Option<Arc<CertificateResolver>>,
+  /**
+    Authenticates calls before they reach a handler
+  */
+  guard: Option<Arc<Guard>>,
+  /**
+    The maximum size of request bodies and websocket messages in bytes
+  */
+  max_body_size: u64,
 }
 
 impl ERPCServer {
   pub fn new(port: u16, allowed_cors_origins: Vec<String>, enabled_sockets: bool) -> Self {
     ERPCServer {
       handlers: Arc::new(tokio::sync::RwLock::new(HashMap::new())),
+      introspection: false,
       shutdown_signal: Arc::new(RwLock::new(None)),
-      port,
+      addresses: vec![SocketAddr::from(([127, 0, 0, 1], port))],
       allowed_cors_origins,
       enabled_sockets,
       socket_channel: flume::unbounded(),
+      sockets: Arc::new(RwLock::new(HashMap::new())),
+      pub_sub: Arc::new(LocalPubSub::default()),
+      tls: None,
+      guard: None,
+      max_body_size: DEFAULT_MAX_BODY_SIZE,
+    }
+  }
+
+  /**
+    Sets the addresses the server listens on, replacing the default of 127.0.0.1 on the configured port.
+    When IPv4 and IPv6 addresses are mixed, the IPv6 sockets only accept IPv6 connections so both can share a port.
+    Otherwise IPv6 sockets accept IPv4 connections as well, e.g. [::] listens on all interfaces of both.
+  */
+  pub fn set_addresses(&mut self, addresses: Vec<SocketAddr>) {
+    self.addresses = addresses;
+  }
+
+  /**
+    Enables TLS with the given PEM encoded certificate chain and private key.
+    Must be called before run to take effect.
+  */
+  pub fn set_tls(&mut self, certificate: &[u8], key: &[u8]) -> Result<(), ERPCError> {
+    self.tls = Some(Arc::new(CertificateResolver::new(certificate, key)?));
+    Ok(())
+  }
+
+  /**
+    Replaces the served certificate without restarting the server. New connections use the new certificate.
+  */
+  pub fn reload_tls(&self, certificate: &[u8], key: &[u8]) -> Result<(), ERPCError> {
+    match &self.tls {
+      Some(v) => v.reload(certificate, key),
+      None => Err(ERPCError::Tls("TLS is not enabled".to_string())),
     }
   }
 
+  /**
+    Sets the maximum size of request bodies and websocket messages in bytes.
+    Larger http requests are rejected with 413 before they are parsed, larger websocket messages close the connection.
+  */
+  pub fn set_max_body_size(&mut self, max_body_size: u64) {
+    self.max_body_size = max_body_size;
+  }
+
+  /**
+    Replaces the pub/sub keeping track of topic subscriptions, e.g. to share one between several servers.
+    Must be called before run to take effect.
+  */
+  pub fn set_pub_sub(&mut self, pub_sub: Arc<dyn PubSub>) {
+    self.pub_sub = pub_sub;
+  }
+
+  /**
+    Sets the guard which is run for every call and socket connection before any handler.
+    Must be called before run to take effect.
+  */
+  pub fn set_guard(&mut self, guard: Guard) {
+    self.guard = Some(Arc::new(guard));
+  }
+
+  /**
+    Enables or disables listing the registered handlers via GET handlers, which is disabled by default.
+    Must be called before run to take effect.
+  */
+  pub fn set_introspection(&mut self, enabled: bool) {
+    self.introspection = enabled;
+  }
+
   #[allow(dead_code)]
   pub fn register_raw_handler(&mut self, handler: Handler, identifier: &str) {
-    self
-      .handlers
-      //TODO: should this become async and not use blocking:write?
-      .blocking_write()
-      .insert(identifier.to_owned(), handler);
+    let handler = Arc::new(handler);
+    //TODO: should this become async and not use blocking:write?
+    let mut handlers = self.handlers.blocking_write();
+    // replacing a handler keeps its metadata
+    match handlers.get_mut(identifier) {
+      Some(v) => v.handler = handler,
+      None => {
+        handlers.insert(
+          identifier.to_owned(),
+          RegisteredHandler {
+            handler,
+            metadata: HandlerMetadata::default(),
+          },
+        );
+      }
+    }
+  }
+
+  /**
+    Attaches metadata like a description or a parameter schema to a registered handler, replacing previous metadata
+  */
+  pub fn describe_handler(
+    &mut self,
+    identifier: &str,
+    metadata: HandlerMetadata,
+  ) -> Result<(), ERPCError> {
+    match self.handlers.blocking_write().get_mut(identifier) {
+      Some(v) => {
+        v.metadata = metadata;
+        Ok(())
+      }
+      None => Err(ERPCError::HandlerNotFound(identifier.to_owned())),
+    }
+  }
+
+  /**
+    The registered handlers with their metadata, ordered by identifier
+  */
+  pub fn handlers(&self) -> Vec<HandlerDescription> {
+    Self::describe_handlers(&self.handlers.blocking_read())
+  }
+
+  fn describe_handlers(handlers: &HashMap<String, RegisteredHandler>) -> Vec<HandlerDescription> {
+    let mut descriptions = handlers
+      .iter()
+      .map(|(identifier, v)| HandlerDescription {
+        identifier: identifier.clone(),
+        metadata: v.metadata.clone(),
+      })
+      .collect::<Vec<_>>();
+    descriptions.sort_by(|a, b| a.identifier.cmp(&b.identifier));
+    descriptions
   }
 
   #[allow(dead_code)]
   pub fn register_handler<H, P>(&mut self, handler: H, identifier: &str)
   where
     H: super::handler::Handler<P> + 'static,
-    P: DeserializeOwned + Send + Sync,
+    P: HandlerArguments + Send + Sync,
     H::Output: Serialize,
     H::Future: Future<Output = H::Output> + Send + Sync,
   {
-    let v: Handler = Box::new(move |v| {
+    let v: Handler = Box::new(move |v, context| {
       let handler = handler.clone();
       Box::pin(async move {
-        //TODO this could be more elegant?
-        let v =
-          serde_json::to_value(v).map_err(|err| format!("Could not convert to value: {err}"))?;
-
-        let parameters = match serde_json::from_value::<P>(v) {
-          Ok(v) => v,
-          Err(err) => {
-            return Err(format!("Failed to parse parameters: {}", err));
-          }
-        };
-
+        let parameters = P::extract(&context, v)?;
         let result = handler.call(parameters).await;
+        Ok(HandlerResponse::Value(serde_json::to_value(result)?))
+      })
+    });
 
-        let serialized = match serde_json::to_value(result) {
-          Ok(v) => v,
-          Err(err) => {
-            return Err(format!("Failed to serialize result: {}", err));
-          }
-        };
+    self.register_raw_handler(v, identifier);
+  }
 
-        Ok(serialized)
+  /**
+    Registers a handler resolving to a stream. Each item is sent to the caller as soon as it is produced.
+    Via http, streams have to be requested with a stream format in the Accept header.
+  */
+  #[allow(dead_code)]
+  pub fn register_stream_handler<H, P>(&mut self, handler: H, identifier: &str)
+  where
+    H: super::handler::Handler<P> + 'static,
+    P: HandlerArguments + Send + Sync,
+    H::Output: Stream + Send + 'static,
+    <H::Output as Stream>::Item: Serialize,
+    H::Future: Future<Output = H::Output> + Send + Sync,
+  {
+    let v: Handler = Box::new(move |v, context| {
+      let handler = handler.clone();
+      Box::pin(async move {
+        let parameters = P::extract(&context, v)?;
+        let stream = handler.call(parameters).await;
+        Ok(HandlerResponse::Stream(Box::pin(
+          stream.map(|v| serde_json::to_value(v).map_err(ERPCError::from)),
+        )))
       })
     });
 
-    self
-      .handlers
-      //TODO: should this become async and not use blocking:write?
-      .blocking_write()
-      .insert(identifier.to_owned(), v);
+    self.register_raw_handler(v, identifier);
   }
 
-  pub fn run(&self) -> Result<impl futures_util::Future<Output = ()> + Send + Sync, String> {
+  /**
+    Binds the configured addresses and returns the addresses actually bound (e.g. when using port 0)
+    together with the future running the server
+  */
+  pub fn run(
+    &self,
+  ) -> Result<
+    (
+      Vec<SocketAddr>,
+      impl futures_util::Future<Output = ()> + Send,
+    ),
+    ERPCError,
+  > {
     let handlers = self.handlers.clone();
     let enabled_sockets = self.enabled_sockets;
     let socket_channel = self.socket_channel.clone();
+    let sockets = self.sockets.clone();
+    let pub_sub = self.pub_sub.clone();
+    let guard = self.guard.clone();
+    let max_body_size = self.max_body_size;
+    let introspection = self.introspection;
+    let listed_handlers = self.handlers.clone();
 
-    let socket_channel = warp::any().map(move || socket_channel.clone());
+    let socket_channel =
+      warp::any().map(move || (socket_channel.clone(), sockets.clone(), pub_sub.clone()));
     let handlers = warp::any().map(move || handlers.clone());
     let enabled_sockets = warp::any().map(move || enabled_sockets);
+    let guard = warp::any().map(move || guard.clone());
+    let max_body_size = warp::any().map(move || max_body_size);
+    let metadata = warp::header::headers_cloned()
+      .and(warp::ext::optional::<RemoteAddress>())
+      .map(|headers, remote_address: Option<RemoteAddress>| {
+        RequestMetadata::new(&headers, remote_address.map(|v| v.0))
+      });
 
     let mut cors = warp::cors()
       .allow_methods(vec![Method::GET, Method::POST])
@@ -136,70 +395,402 @@ impl ERPCServer {
       }
     }
 
-    let http = warp::path!("handlers" / ..)
-      .and(handlers)
+    let listing = warp::path!("handlers")
+      .and(warp::get())
+      .and(warp::any().map(move || (introspection, listed_handlers.clone())))
+      .and(guard.clone())
+      .and(metadata)
+      .then(Self::listing_handler)
+      .with(cors.clone());
+
+    // notifications are handled like calls but answered before the handler runs
+    let notification = warp::path!("handlers" / ..)
+      .map(|| false)
+      .or(warp::path!("notifications" / ..).map(|| true))
+      .unify();
+    let http = notification
+      .and(handlers.clone())
+      .and(guard.clone())
+      .and(metadata)
       .and(warp::path::peek())
-      .and(warp::body::json())
-      .and(warp::body::content_length_limit(1024 * 64))
+      .and(max_body_size)
+      .and(warp::body::stream())
       .then(Self::http_handler)
       .with(cors.clone());
 
+    let batch = warp::path!("batch")
+      .and(handlers.clone())
+      .and(guard.clone())
+      .and(metadata)
+      .and(max_body_size)
+      .and(warp::body::stream())
+      .then(Self::batch_handler)
+      .with(cors.clone());
+
     let handlers = self.handlers.clone();
     let request_handlers = warp::any().map(move || handlers.clone());
     let ws = warp::path!("ws" / String)
       .and(enabled_sockets)
       .and(request_handlers)
+      .and(guard)
+      .and(metadata)
       .and(socket_channel)
-      .and(warp::ws())
-      .map(
-        |role, enabled_sockets, request_handlers, socket_channel, ws| {
-          Self::socket_handler(role, enabled_sockets, request_handlers, socket_channel, ws)
-        },
+      .and(
+        warp::ws()
+          .and(max_body_size)
+          .and(warp::query::<HashMap<String, String>>())
+          .map(
+            |ws: warp::ws::Ws, size: u64, mut query: HashMap<String, String>| {
+              (
+                ws.max_message_size(size as usize)
+                  .max_frame_size(size as usize),
+                query.remove("encoding"),
+                query.remove("version"),
+              )
+            },
+          ),
       )
+      .then(Self::socket_handler)
       .with(cors.clone());
 
+    let listeners = self.bind()?;
+    let addresses = listeners
+      .iter()
+      .map(|v| v.local_addr())
+      .collect::<Result<Vec<_>, _>>()
+      .map_err(|err| ERPCError::Transport(format!("Could not read bound address: {err}")))?;
+
     let (sender, reciever) = oneshot::channel::<()>();
     self
       .shutdown_signal
       .write()
-      .map_err(|err| format!("Could not set shutdown signal: {err}"))?
+      .map_err(|err| ERPCError::Internal(format!("Could not set shutdown signal: {err}")))?
       .replace(sender);
+    let shutdown = async {
+      reciever.await.ok();
+    }
+    .shared();
 
-    let (_, server) = warp::serve(http.or(ws).with(cors)).bind_with_graceful_shutdown(
-      ([127, 0, 0, 1], self.port),
-      async {
-        reciever.await.ok();
-      },
-    );
+    let service = warp::service(listing.or(http).or(batch).or(ws).with(cors));
+    let acceptor = self
+      .tls
+      .as_ref()
+      .map(|v| TlsAcceptor::from(Arc::new(v.server_config())));
+    let servers = listeners
+      .into_iter()
+      .map(|listener| {
+        let service = service.clone();
+        let server = match &acceptor {
+          Some(acceptor) => {
+            let listener = tokio::net::TcpListener::from_std(listener)
+              .map_err(|err| ERPCError::Transport(format!("Could not start server: {err}")))?;
+            let incoming = Self::tls_incoming(listener, acceptor.clone(), shutdown.clone());
+            tokio::spawn(
+              hyper::Server::builder(incoming)
+                .serve(make_service_fn(move |stream: &TlsStream<TcpStream>| {
+                  let service =
+                    Self::with_remote_address(service.clone(), stream.get_ref().0.peer_addr().ok());
+                  async move { Ok::<_, Infallible>(service) }
+                }))
+                .with_graceful_shutdown(shutdown.clone()),
+            )
+          }
+          None => tokio::spawn(
+            hyper::Server::from_tcp(listener)
+              .map_err(|err| ERPCError::Transport(format!("Could not start server: {err}")))?
+              .serve(make_service_fn(move |stream: &AddrStream| {
+                let service =
+                  Self::with_remote_address(service.clone(), Some(stream.remote_addr()));
+                async move { Ok::<_, Infallible>(service) }
+              }))
+              .with_graceful_shutdown(shutdown.clone()),
+          ),
+        };
+        Ok(server)
+      })
+      .collect::<Result<Vec<_>, ERPCError>>()?;
+
+    Ok((addresses, async move {
+      for result in futures_util::future::join_all(servers).await {
+        match result {
+          Ok(Ok(_)) => {}
+          Ok(Err(err)) => eprintln!("Server error: {err}"),
+          Err(err) => eprintln!("Server task error: {err}"),
+        }
+      }
+    }))
+  }
+
+  /**
+    Attaches the address of the client to each request, so filters can read it
+  */
+  fn with_remote_address<S>(
+    service: S,
+    remote_address: Option<SocketAddr>,
+  ) -> impl Service<
+    hyper::Request<hyper::Body>,
+    Response = hyper::Response<hyper::Body>,
+    Error = Infallible,
+    Future = S::Future,
+  > + Clone
+  where
+    S: Service<
+        hyper::Request<hyper::Body>,
+        Response = hyper::Response<hyper::Body>,
+        Error = Infallible,
+      > + Clone,
+  {
+    service_fn(move |mut request: hyper::Request<hyper::Body>| {
+      if let Some(v) = remote_address {
+        request.extensions_mut().insert(RemoteAddress(v));
+      }
+      service.clone().call(request)
+    })
+  }
+
+  /**
+    Runs the guard, if any, and returns the context the handler is called with
+  */
+  async fn authorize(
+    guard: &Option<Arc<Guard>>,
+    request_id: String,
+    metadata: RequestMetadata,
+  ) -> Result<RequestContext, ERPCError> {
+    let auth = match guard {
+      Some(guard) => guard(metadata.clone()).await?,
+      None => None,
+    };
+    Ok(RequestContext::new(request_id, metadata, auth))
+  }
+
+  /**
+    Accepts connections on the listener and performs the TLS handshakes without blocking the accept loop
+  */
+  fn tls_incoming(
+    listener: tokio::net::TcpListener,
+    acceptor: TlsAcceptor,
+    shutdown: impl Future<Output = ()> + Send + Unpin + 'static,
+  ) -> impl accept::Accept<Conn = TlsStream<TcpStream>, Error = std::io::Error> {
+    let (sender, reciever) = flume::unbounded();
+
+    tokio::spawn(async move {
+      let mut shutdown = shutdown;
+      loop {
+        let stream = tokio::select! {
+          v = listener.accept() => match v {
+            Ok((stream, _)) => stream,
+            Err(err) => {
+              eprintln!("Could not accept connection: {err}");
+              tokio::time::sleep(ACCEPT_ERROR_DELAY).await;
+              continue;
+            }
+          },
+          _ = &mut shutdown => break,
+        };
+
+        let acceptor = acceptor.clone();
+        let sender = sender.clone();
+        tokio::spawn(async move {
+          match tokio::time::timeout(TLS_HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+            Ok(Ok(v)) => {
+              sender.send(Ok(v)).ok();
+            }
+            Ok(Err(err)) => eprintln!("TLS handshake failed: {err}"),
+            Err(_) => eprintln!("TLS handshake timed out"),
+          };
+        });
+      }
+    });
+
+    accept::from_stream(reciever.into_stream())
+  }
+
+  fn bind(&self) -> Result<Vec<TcpListener>, ERPCError> {
+    let mixed =
+      self.addresses.iter().any(|v| v.is_ipv4()) && self.addresses.iter().any(|v| v.is_ipv6());
+
+    self
+      .addresses
+      .iter()
+      .map(|address| {
+        let bind = || -> std::io::Result<TcpListener> {
+          let socket = socket2::Socket::new(
+            socket2::Domain::for_address(*address),
+            socket2::Type::STREAM,
+            None,
+          )?;
+          // set explicitly, as whether IPv6 sockets accept IPv4 connections by default depends on the OS
+          if address.is_ipv6() {
+            socket.set_only_v6(mixed)?;
+          }
+          #[cfg(unix)]
+          socket.set_reuse_address(true)?;
+          socket.set_nonblocking(true)?;
+          socket.bind(&(*address).into())?;
+          socket.listen(1024)?;
+          Ok(socket.into())
+        };
 
-    Ok(server)
+        bind().map_err(|err| ERPCError::Transport(format!("Could not bind to {address}: {err}")))
+      })
+      .collect()
   }
 
-  pub fn stop(&self) -> Result<(), String> {
+  pub fn stop(&self) -> Result<(), ERPCError> {
     let mut w = self
       .shutdown_signal
       .write()
-      .map_err(|err| format!("Could not set shutdown signal: {err}"))?;
+      .map_err(|err| ERPCError::Internal(format!("Could not set shutdown signal: {err}")))?;
     let sender = match w.take() {
       Some(v) => v,
       None => {
-        return Err("Server is not running".to_string());
+        return Err(ERPCError::Internal("Server is not running".to_string()));
       }
     };
 
     match sender.send(()) {
       Ok(_) => {}
       Err(err) => {
-        return Err(format!(
+        return Err(ERPCError::Internal(format!(
           "Server can't be stopped because of error: {:#?}",
           err
-        ));
+        )));
       }
     };
 
     Ok(())
   }
 
+  /**
+    The live sockets connected with the given role
+  */
+  #[allow(dead_code)]
+  pub fn sockets(&self, role: &str) -> Vec<Socket> {
+    match self.sockets.read() {
+      Ok(v) => v
+        .get(role)
+        .map(|v| v.values().cloned().collect())
+        .unwrap_or_default(),
+      Err(err) => {
+        eprintln!("Could not access socket registry: {err}");
+        Vec::new()
+      }
+    }
+  }
+
+  /**
+    Calls the handler on every live socket of the role and returns the result of each call by socket id.
+    Calls fail individually, e.g. with a timeout error when the given duration passes without a response.
+  */
+  pub async fn broadcast<P: Serialize>(
+    &self,
+    role: &str,
+    identifier: String,
+    parameters: Vec<P>,
+    timeout: Option<Duration>,
+  ) -> Result<Vec<(String, Result<serde_json::Value, ERPCError>)>, ERPCError> {
+    Self::call_sockets(self.sockets(role), identifier, parameters, timeout).await
+  }
+
+  /**
+    Subscribes a live socket of this server to the topic
+  */
+  pub fn subscribe(&self, socket_id: &str, topic: &str) -> Result<(), ERPCError> {
+    let socket = self
+      .sockets
+      .read()
+      .map_err(|err| ERPCError::Internal(format!("Could not access socket registry: {err}")))?
+      .values()
+      .find_map(|v| v.get(socket_id).cloned())
+      .ok_or(ERPCError::SocketClosed)?;
+    self.pub_sub.subscribe(topic, socket);
+    Ok(())
+  }
+
+  pub fn unsubscribe(&self, socket_id: &str, topic: &str) {
+    self.pub_sub.unsubscribe(topic, socket_id);
+  }
+
+  /**
+    Sends a notification to every socket subscribed to the topic, including sockets of other servers sharing the pub/sub.
+    Returns the number of sockets it has been sent to.
+  */
+  pub fn publish<P: Serialize>(
+    &self,
+    topic: &str,
+    identifier: String,
+    parameters: Vec<P>,
+  ) -> Result<usize, ERPCError> {
+    let parameters = parameters
+      .iter()
+      .map(serde_json::to_value)
+      .collect::<Result<Vec<_>, _>>()?;
+    let sent = self
+      .pub_sub
+      .subscribers(topic)
+      .iter()
+      .filter(|socket| {
+        socket
+          .notify(protocol::Request {
+            identifier: identifier.clone(),
+            parameters: parameters.clone(),
+          })
+          .is_ok()
+      })
+      .count();
+    Ok(sent)
+  }
+
+  /**
+    Calls the handler on every socket subscribed to the topic and returns the result of each call by socket id, like broadcast
+  */
+  pub async fn publish_call<P: Serialize>(
+    &self,
+    topic: &str,
+    identifier: String,
+    parameters: Vec<P>,
+    timeout: Option<Duration>,
+  ) -> Result<Vec<(String, Result<serde_json::Value, ERPCError>)>, ERPCError> {
+    Self::call_sockets(
+      self.pub_sub.subscribers(topic),
+      identifier,
+      parameters,
+      timeout,
+    )
+    .await
+  }
+
+  async fn call_sockets<P: Serialize>(
+    sockets: Vec<Socket>,
+    identifier: String,
+    parameters: Vec<P>,
+    timeout: Option<Duration>,
+  ) -> Result<Vec<(String, Result<serde_json::Value, ERPCError>)>, ERPCError> {
+    let parameters = parameters
+      .iter()
+      .map(serde_json::to_value)
+      .collect::<Result<Vec<_>, _>>()?;
+
+    let calls = sockets.into_iter().map(|socket| {
+      let request = protocol::Request {
+        identifier: identifier.clone(),
+        parameters: parameters.clone(),
+      };
+      async move {
+        let call = socket.call(request);
+        let result = match timeout {
+          Some(timeout) => tokio::time::timeout(timeout, call)
+            .await
+            .unwrap_or(Err(ERPCError::Timeout)),
+          None => call.await,
+        };
+        (socket.id, result)
+      }
+    });
+
+    Ok(futures_util::future::join_all(calls).await)
+  }
+
   /**
     A channel containing all previously connected sockets and broadcasting new socket connections
   */
@@ -208,110 +799,872 @@ impl ERPCServer {
   }
 
   //TODO remove return type of Box<dyn Reply> and replace with static types
-  async fn http_handler(
-    request_handlers: Arc<tokio::sync::RwLock<HashMap<String, Handler>>>,
+  async fn http_handler<S, B>(
+    notification: bool,
+    request_handlers: Handlers,
+    guard: Option<Arc<Guard>>,
+    mut metadata: RequestMetadata,
     path: Peek,
+    max_body_size: u64,
+    body: S,
+  ) -> Box<dyn Reply>
+  where
+    S: Stream<Item = Result<B, warp::Error>>,
+    B: Buf,
+  {
+    let version = match protocol::negotiate_version(
+      metadata
+        .headers
+        .get(protocol::VERSION_HEADER)
+        .map(String::as_str),
+    ) {
+      Ok(v) => v,
+      Err(err) => {
+        let reply = Self::http_reply(path.as_str(), Err(err), Encoding::default());
+        return Box::new(Self::with_version(reply, protocol::PROTOCOL_VERSION));
+      }
+    };
+    metadata.protocol_version = version;
+    metadata.identifier = Some(path.as_str().to_owned());
+    // unknown content types are read as json to stay compatible with clients not setting one
+    let encoding = metadata
+      .headers
+      .get("content-type")
+      .and_then(|v| Encoding::from_content_type(v))
+      .unwrap_or_default();
+    let response_encoding = metadata
+      .headers
+      .get("accept")
+      .and_then(|v| Encoding::from_accept(v))
+      .unwrap_or(encoding);
+    let stream_format = metadata
+      .headers
+      .get("accept")
+      .and_then(|v| StreamFormat::from_accept(v));
+    let envelope = Self::read_body(body, max_body_size, encoding)
+      .await
+      .and_then(|v| protocol::Envelope::from_body(v, path.as_str(), version));
+    let request_id = envelope
+      .as_ref()
+      .ok()
+      .and_then(|v| v.id.clone())
+      .unwrap_or_else(|| nanoid::nanoid!());
+    // authorization errors take precedence over invalid bodies
+    let (result, response_headers) =
+      match Self::authorize(&guard, request_id.clone(), metadata).await {
+        Ok(context) => {
+          let result = match envelope {
+            Ok(envelope) => {
+              let handler = request_handlers
+                .read()
+                .await
+                .get(path.as_str())
+                .map(|v| v.handler.clone());
+              match handler {
+                Some(handler) if notification => {
+                  Self::spawn_notification(
+                    handler,
+                    envelope.parameters,
+                    context,
+                    path.as_str().to_owned(),
+                  );
+                  return Box::new(Self::with_version(StatusCode::ACCEPTED, version));
+                }
+                Some(handler) => handler(envelope.parameters, context.clone()).await,
+                None => Err(ERPCError::HandlerNotFound(path.as_str().to_owned())),
+              }
+            }
+            Err(err) => Err(err),
+          };
+          (result, context.response_headers())
+        }
+        Err(err) => (Err(err), Vec::new()),
+      };
+
+    let identifier = path.as_str();
+    let response = match (result, stream_format) {
+      (Ok(response), Some(format)) => {
+        let stream = match response {
+          HandlerResponse::Stream(v) => v,
+          // a single value is streamed as the only item
+          HandlerResponse::Value(v) => Box::pin(futures_util::stream::once(async { Ok(v) })),
+        };
+        Self::stream_reply(
+          Self::stream_messages(request_id, identifier.to_owned(), stream),
+          format,
+        )
+      }
+      (Ok(HandlerResponse::Stream(_)), None) => {
+        eprintln!("Rejected call of streaming handler {identifier} without stream format");
+        Box::new(warp::reply::with_status(
+          format!(
+            "{identifier} streams its response, request it with Accept: {} or {}",
+            StreamFormat::EventStream.content_type(),
+            StreamFormat::Ndjson.content_type()
+          ),
+          StatusCode::NOT_ACCEPTABLE,
+        ))
+      }
+      (Ok(HandlerResponse::Value(v)), None) => {
+        Self::http_reply(identifier, Ok(v), response_encoding)
+      }
+      (Err(err), _) => Self::http_reply(identifier, Err(err), response_encoding),
+    };
+
+    let mut response = response.into_response();
+    for (name, value) in response_headers {
+      match (
+        HeaderName::from_bytes(name.as_bytes()),
+        HeaderValue::from_str(&value),
+      ) {
+        (Ok(name), Ok(value)) => {
+          response.headers_mut().append(name, value);
+        }
+        _ => eprintln!("Ignoring invalid response header {name}: {value}"),
+      }
+    }
+    Box::new(Self::with_version(response, version))
+  }
+
+  /**
+    Lists the registered handlers, if enabled. Callers have to pass the guard like for any call.
+  */
+  async fn listing_handler(
+    (enabled, handlers): (bool, Handlers),
+    guard: Option<Arc<Guard>>,
+    metadata: RequestMetadata,
+  ) -> Box<dyn Reply> {
+    if !enabled {
+      return Box::new(warp::reply::with_status(
+        "Handler listing is disabled",
+        StatusCode::NOT_FOUND,
+      ));
+    }
+
+    let encoding = metadata
+      .headers
+      .get("accept")
+      .and_then(|v| Encoding::from_accept(v))
+      .unwrap_or_default();
+    let result = match Self::authorize(&guard, nanoid::nanoid!(), metadata).await {
+      Ok(_) => serde_json::to_value(Self::describe_handlers(&*handlers.read().await))
+        .map_err(ERPCError::from),
+      Err(err) => Err(err),
+    };
+    Self::http_reply("handlers", result, encoding)
+  }
+
+  /**
+    Runs the calls of a batch request concurrently and answers with their results in the same order.
+    The body is an array of request envelopes, see protocol::Envelope.
+    Each call is authorized on its own, a failing call does not affect the others.
+  */
+  async fn batch_handler<S, B>(
+    request_handlers: Handlers,
+    guard: Option<Arc<Guard>>,
+    mut metadata: RequestMetadata,
+    max_body_size: u64,
+    body: S,
+  ) -> Box<dyn Reply>
+  where
+    S: Stream<Item = Result<B, warp::Error>>,
+    B: Buf,
+  {
+    let version = match protocol::negotiate_version(
+      metadata
+        .headers
+        .get(protocol::VERSION_HEADER)
+        .map(String::as_str),
+    ) {
+      Ok(v) => v,
+      Err(err) => {
+        let reply = Self::http_reply("batch", Err(err), Encoding::default());
+        return Box::new(Self::with_version(reply, protocol::PROTOCOL_VERSION));
+      }
+    };
+    metadata.protocol_version = version;
+    let encoding = metadata
+      .headers
+      .get("content-type")
+      .and_then(|v| Encoding::from_content_type(v))
+      .unwrap_or_default();
+    let response_encoding = metadata
+      .headers
+      .get("accept")
+      .and_then(|v| Encoding::from_accept(v))
+      .unwrap_or(encoding);
+    let items: Vec<serde_json::Value> = match Self::read_body(body, max_body_size, encoding).await {
+      Ok(v) => v,
+      Err(err) => {
+        let reply = Self::http_reply("batch", Err(err), response_encoding);
+        return Box::new(Self::with_version(reply, version));
+      }
+    };
+
+    // every item is a request envelope, invalid ones fail on their own
+    let calls = items.into_iter().map(|item| {
+      let request_handlers = request_handlers.clone();
+      let guard = guard.clone();
+      let mut metadata = metadata.clone();
+      async move {
+        let envelope = match protocol::Envelope::from_value(item, version) {
+          Ok(v) => v,
+          Err(err) => return (Self::batch_result("batch", Err(err)), Vec::new()),
+        };
+        let identifier = envelope.identifier;
+        let request_id = envelope.id.unwrap_or_else(|| nanoid::nanoid!());
+        metadata.identifier = Some(identifier.clone());
+        match Self::authorize(&guard, request_id, metadata).await {
+          Ok(context) => {
+            let handler = request_handlers
+              .read()
+              .await
+              .get(&identifier)
+              .map(|v| v.handler.clone());
+            let result = match handler {
+              Some(handler) => handler(envelope.parameters, context.clone()).await,
+              None => Err(ERPCError::HandlerNotFound(identifier.clone())),
+            };
+            (
+              Self::batch_result(&identifier, result),
+              context.response_headers(),
+            )
+          }
+          Err(err) => (Self::batch_result(&identifier, Err(err)), Vec::new()),
+        }
+      }
+    });
+    let (results, response_headers): (Vec<_>, Vec<_>) = futures_util::future::join_all(calls)
+      .await
+      .into_iter()
+      .unzip();
+
+    let mut response = match response_encoding.serialize(&results) {
+      Ok(body) => warp::reply::with_header(body, "content-type", response_encoding.content_type())
+        .into_response(),
+      Err(err) => return Self::http_reply("batch", Err(err), response_encoding),
+    };
+    for (name, value) in response_headers.into_iter().flatten() {
+      match (
+        HeaderName::from_bytes(name.as_bytes()),
+        HeaderValue::from_str(&value),
+      ) {
+        (Ok(name), Ok(value)) => {
+          response.headers_mut().append(name, value);
+        }
+        _ => eprintln!("Ignoring invalid response header {name}: {value}"),
+      }
+    }
+    Box::new(Self::with_version(response, version))
+  }
+
+  /**
+    Tells the caller which protocol version the response has been made with
+  */
+  fn with_version(reply: impl Reply, version: u32) -> warp::reply::Response {
+    let mut response = reply.into_response();
+    response
+      .headers_mut()
+      .insert(protocol::VERSION_HEADER, HeaderValue::from(version));
+    response
+  }
+
+  /**
+    Answers a call of a batch like http_reply would, with the status and body of the response
+  */
+  fn batch_result(
+    identifier: &str,
+    result: Result<HandlerResponse, ERPCError>,
+  ) -> protocol::BatchResult {
+    let (status, body) = match result {
+      Ok(HandlerResponse::Value(v)) => (StatusCode::OK, v),
+      Ok(HandlerResponse::Stream(_)) => (
+        StatusCode::NOT_ACCEPTABLE,
+        serde_json::Value::String(format!(
+          "{identifier} streams its response and can not be called in a batch"
+        )),
+      ),
+      Err(ERPCError::Application(err)) => (
+        StatusCode::UNPROCESSABLE_ENTITY,
+        serde_json::to_value(err).unwrap_or_default(),
+      ),
+      Err(err) => (
+        err.status_code(),
+        serde_json::Value::String(Self::error_message(identifier, &err)),
+      ),
+    };
+    protocol::BatchResult {
+      status: status.as_u16(),
+      body,
+    }
+  }
+
+  /**
+    Runs the handler of a notification in the background, its result is dropped
+  */
+  fn spawn_notification(
+    handler: Arc<Handler>,
     parameters: Vec<serde_json::Value>,
+    context: RequestContext,
+    identifier: String,
+  ) {
+    tokio::spawn(async move {
+      if let Err(err) = handler(parameters, context).await {
+        eprintln!("Error while running notification handler {identifier}: {err}");
+      }
+    });
+  }
+
+  /**
+    Reads and parses the request body, failing as soon as it exceeds the limit
+  */
+  async fn read_body<S, B, T>(body: S, limit: u64, encoding: Encoding) -> Result<T, ERPCError>
+  where
+    S: Stream<Item = Result<B, warp::Error>>,
+    B: Buf,
+    T: DeserializeOwned,
+  {
+    futures_util::pin_mut!(body);
+    let mut data = Vec::new();
+    while let Some(chunk) = body.next().await {
+      let mut chunk =
+        chunk.map_err(|err| ERPCError::Transport(format!("Could not read request body: {err}")))?;
+      if (data.len() + chunk.remaining()) as u64 > limit {
+        return Err(ERPCError::PayloadTooLarge(format!(
+          "The request body exceeds the limit of {limit} bytes"
+        )));
+      }
+      while chunk.has_remaining() {
+        let part = chunk.chunk();
+        let length = part.len();
+        data.extend_from_slice(part);
+        chunk.advance(length);
+      }
+    }
+
+    encoding.deserialize(&data)
+  }
+
+  fn http_reply(
+    identifier: &str,
+    result: Result<serde_json::Value, ERPCError>,
+    encoding: Encoding,
   ) -> Box<dyn Reply> {
-    let lock = request_handlers.read().await;
-    let handler = match lock.get(path.as_str()) {
-      Some(v) => v,
-      None => {
-        eprintln!("Could not find a registered handler for {}", path.as_str());
-        return Box::new(warp::reply::with_status(
-          "Internal server error. Please see server logs",
-          StatusCode::NOT_FOUND,
-        ));
+    let encoded = match result {
+      Ok(v) => encoding.serialize(&v).map(|v| (v, StatusCode::OK)),
+      Err(ERPCError::Application(err)) => encoding
+        .serialize(&err)
+        .map(|v| (v, StatusCode::UNPROCESSABLE_ENTITY)),
+      Err(err) => Err(err),
+    };
+
+    match encoded {
+      Ok((body, status)) => Box::new(warp::reply::with_status(
+        warp::reply::with_header(body, "content-type", encoding.content_type()),
+        status,
+      )),
+      Err(err) => Box::new(warp::reply::with_status(
+        Self::error_message(identifier, &err),
+        err.status_code(),
+      )),
+    }
+  }
+
+  /**
+    Logs an error of a handler and returns the message the caller is answered with, hiding internal errors
+  */
+  fn error_message(identifier: &str, err: &ERPCError) -> String {
+    eprintln!("Error while running handler {identifier}: {err}");
+    match err {
+      ERPCError::HandlerFailed(_) | ERPCError::Internal(_) | ERPCError::Tls(_) => {
+        "Internal server error. Please see server logs".to_string()
+      }
+      ERPCError::Unauthorized(v) | ERPCError::Forbidden(v) | ERPCError::PayloadTooLarge(v) => {
+        v.clone()
+      }
+      _ => err.to_string(),
+    }
+  }
+
+  /**
+    Turns the items of a streamed response into the messages sent to the caller, ending with a StreamEnd or StreamError
+  */
+  fn stream_messages(
+    id: String,
+    identifier: String,
+    stream: ResponseStream,
+  ) -> impl Stream<Item = SocketMessage> + Send + 'static {
+    futures_util::stream::unfold(Some(stream), move |stream| {
+      let id = id.clone();
+      let identifier = identifier.clone();
+      async move {
+        let mut stream = stream?;
+        match stream.next().await {
+          Some(Ok(v)) => Some((
+            SocketMessage::StreamItem(StreamItem { id, item: v }),
+            Some(stream),
+          )),
+          Some(Err(err)) => Some((
+            SocketMessage::StreamError(StreamError {
+              id,
+              error: Self::socket_error(&identifier, err),
+            }),
+            None,
+          )),
+          None => Some((SocketMessage::StreamEnd(StreamEnd { id, done: true }), None)),
+        }
+      }
+    })
+  }
+
+  /**
+    Sends stream messages as server-sent events or newline delimited json
+  */
+  fn stream_reply(
+    messages: impl Stream<Item = SocketMessage> + Send + 'static,
+    format: StreamFormat,
+  ) -> Box<dyn Reply> {
+    match format {
+      StreamFormat::EventStream => Box::new(warp::sse::reply(messages.map(|message| {
+        let event = match message {
+          SocketMessage::StreamItem(_) => "StreamItem",
+          SocketMessage::StreamError(_) => "StreamError",
+          SocketMessage::StreamEnd(_) => "StreamEnd",
+          _ => "message",
+        };
+        serde_json::to_string(&message).map(|v| warp::sse::Event::default().event(event).data(v))
+      }))),
+      StreamFormat::Ndjson => Box::new(warp::reply::with_header(
+        warp::reply::Response::new(hyper::Body::wrap_stream(messages.map(|message| {
+          serde_json::to_vec(&message).map(|mut v| {
+            v.push(b'\n');
+            v
+          })
+        }))),
+        "content-type",
+        format.content_type(),
+      )),
+    }
+  }
+
+  /**
+    Runs the handler of a socket request and sends its response, or each item when it streams its response
+  */
+  async fn socket_request_handler(
+    request_handlers: Handlers,
+    guard: Option<Arc<Guard>>,
+    mut metadata: RequestMetadata,
+    request: protocol::socket::Request,
+    response_sender: flume::Sender<SocketMessage>,
+  ) -> Result<(), flume::SendError<SocketMessage>> {
+    let identifier = request.request.identifier.clone();
+    metadata.identifier = Some(identifier.clone());
+    let result = match Self::authorize(&guard, request.id.clone(), metadata).await {
+      Ok(context) => {
+        let handler = request_handlers
+          .read()
+          .await
+          .get(&identifier)
+          .map(|v| v.handler.clone());
+        match handler {
+          Some(handler) => handler(request.request.parameters, context).await,
+          None => Err(ERPCError::HandlerNotFound(identifier.clone())),
+        }
       }
+      Err(err) => Err(err),
     };
 
-    let result = match handler(parameters).await {
-      Ok(v) => v,
+    let stream = match result {
+      Ok(HandlerResponse::Stream(v)) => v,
+      Ok(HandlerResponse::Value(v)) => {
+        return response_sender.send(SocketMessage::Response(protocol::socket::Response {
+          id: request.id,
+          body: Ok(protocol::Response {
+            body: v,
+            error: None,
+          }),
+        }));
+      }
       Err(err) => {
-        eprintln!("Error while running handler {}: {err}", path.as_str());
-        return Box::new(warp::reply::with_status(
-          "Internal server error. Please see server logs",
-          StatusCode::INTERNAL_SERVER_ERROR,
-        ));
+        return response_sender.send(SocketMessage::Response(protocol::socket::Response {
+          id: request.id,
+          body: Self::socket_error(&identifier, err).map(|err| protocol::Response {
+            body: serde_json::Value::Null,
+            error: Some(err),
+          }),
+        }));
       }
     };
 
-    Box::new(warp::reply::json(&result))
+    let messages = Self::stream_messages(request.id, identifier, stream);
+    futures_util::pin_mut!(messages);
+    while let Some(message) = messages.next().await {
+      // a failed send means the socket closed, dropping the stream stops the handler
+      response_sender.send(message)?;
+    }
+    Ok(())
+  }
+
+  /**
+    Splits errors sent via socket into application errors and internal error strings
+  */
+  fn socket_error(identifier: &str, err: ERPCError) -> Result<protocol::ApplicationError, String> {
+    match err {
+      ERPCError::Application(err) => Ok(err),
+      err => {
+        eprintln!("Error while running handler {identifier}: {err}");
+        Err(err.to_string())
+      }
+    }
+  }
+
+  /**
+    Picks the encoding of a socket connection from the encoding query parameter or the offered subprotocols.
+    Returns the subprotocol to accept, if the client offered a supported one.
+  */
+  fn socket_encoding(
+    metadata: &RequestMetadata,
+    requested: Option<String>,
+  ) -> Result<(Encoding, Option<Encoding>), ERPCError> {
+    let subprotocol = metadata
+      .headers
+      .get("sec-websocket-protocol")
+      .and_then(|v| v.split(',').find_map(Encoding::from_name));
+
+    match requested {
+      Some(v) => match Encoding::from_name(&v) {
+        Some(encoding) => Ok((encoding, subprotocol.filter(|v| *v == encoding))),
+        None => Err(ERPCError::Serialization(format!(
+          "Unsupported encoding {v}"
+        ))),
+      },
+      None => Ok((subprotocol.unwrap_or_default(), subprotocol)),
+    }
   }
 
-  fn socket_handler(
+  async fn socket_handler(
     role: String,
     enabled_sockets: bool,
-    _request_handlers: Arc<tokio::sync::RwLock<HashMap<String, Handler>>>, // in the future we might also handle requests incoming via sockets
-    socket_channel: SocketChannel,
-    ws: warp::ws::Ws,
+    request_handlers: Handlers,
+    guard: Option<Arc<Guard>>,
+    mut metadata: RequestMetadata,
+    (socket_channel, sockets, pub_sub): (SocketChannel, SocketRegistry, Arc<dyn PubSub>),
+    (ws, requested_encoding, requested_version): (warp::ws::Ws, Option<String>, Option<String>),
   ) -> Box<dyn Reply> {
     if enabled_sockets {
-      Box::new(ws.on_upgrade(|socket| async move {
+      // browsers can not set headers on websockets, so the version can be requested via query as well
+      let requested_version =
+        requested_version.or_else(|| metadata.headers.get(protocol::VERSION_HEADER).cloned());
+      let version = match protocol::negotiate_version(requested_version.as_deref()) {
+        Ok(v) => v,
+        Err(err) => {
+          eprintln!("Rejected socket connection with role {role}: {err}");
+          let reply = warp::reply::with_status(err.to_string(), err.status_code());
+          return Box::new(Self::with_version(reply, protocol::PROTOCOL_VERSION));
+        }
+      };
+      metadata.protocol_version = version;
+
+      let (encoding, subprotocol) = match Self::socket_encoding(&metadata, requested_encoding) {
+        Ok(v) => v,
+        Err(err) => return Box::new(warp::reply::with_status(err.to_string(), err.status_code())),
+      };
+
+      metadata.role = Some(role.clone());
+      if let Err(err) = Self::authorize(&guard, nanoid::nanoid!(), metadata.clone()).await {
+        eprintln!("Rejected socket connection with role {role}: {err}");
+        let message = match err {
+          ERPCError::Unauthorized(ref v) | ERPCError::Forbidden(ref v) => v.clone(),
+          _ => "Internal server error. Please see server logs".to_string(),
+        };
+        return Box::new(warp::reply::with_status(message, err.status_code()));
+      }
+
+      let upgrade = ws.on_upgrade(move |socket| async move {
         let (mut socket_sender, mut socket_reciever) = socket.split();
         let (incoming_sender, incoming_reciever) = flume::unbounded::<SocketMessage>();
         let (outgoing_sender, outgoing_reciever) = flume::unbounded::<SocketMessage>();
+        let (event_sender, event_reciever) = flume::unbounded::<SocketEvent>();
+        let (close_sender, close_reciever) = flume::unbounded::<(u16, String)>();
+        let (reader_done_sender, mut reader_done_reciever) = oneshot::channel::<()>();
+
+        let socket = Socket {
+          id: nanoid::nanoid!(),
+          sender: outgoing_sender.clone(),
+          reciever: incoming_reciever.clone(),
+          role: role.clone(),
+          version,
+          events: event_reciever,
+          closer: close_sender,
+          calls: Default::default(),
+        };
+        match sockets.write() {
+          Ok(mut v) => {
+            v.entry(role.clone())
+              .or_default()
+              .insert(socket.id.clone(), socket.clone());
+          }
+          Err(err) => eprintln!("Could not register socket: {err}"),
+        }
+        let reader_socket = socket.clone();
 
+        let response_sender = outgoing_sender.clone();
+        let reader_event_sender = event_sender.clone();
         tokio::spawn(async move {
-          while let Some(message) = socket_reciever.next().await {
-            let message = match message {
-              Ok(v) => {
-                let m: SocketMessage = match serde_json::from_slice(v.as_bytes()) {
-                  Ok(v) => v,
-                  Err(err) => {
-                    eprintln!("Websocket message parse error: {err}");
-                    return;
-                  }
-                };
-                m
+          let mut close_frame = (None, String::new());
+          loop {
+            let message = match socket_reciever.next().await {
+              Some(Ok(v)) => v,
+              Some(Err(err)) => {
+                eprintln!("Websocket message error: {err}");
+                reader_event_sender
+                  .send(SocketEvent::Error(format!(
+                    "Websocket message error: {err}"
+                  )))
+                  .ok();
+                break;
+              }
+              None => break,
+            };
+
+            if message.is_close() {
+              if let Some((code, reason)) = message.close_frame() {
+                close_frame = (Some(code), reason.to_owned());
               }
+              break;
+            }
+            if message.is_ping() || message.is_pong() {
+              continue;
+            }
+
+            let (data, is_binary) = (message.as_bytes(), message.is_binary());
+            let message = match SocketMessage::parse(data, is_binary, encoding, version) {
+              Ok(v) => v,
               Err(err) => {
-                eprintln!("Websocket message error: {err}");
-                return;
+                eprintln!("Websocket message parse error: {err}");
+                reader_event_sender
+                  .send(SocketEvent::Error(format!(
+                    "Websocket message parse error: {err}"
+                  )))
+                  .ok();
+                // legacy peers do not understand error frames, their malformed frames are skipped
+                if version >= TAGGED_VERSION {
+                  let frame = ProtocolError {
+                    id: SocketMessage::malformed_id(data, is_binary, encoding),
+                    message: err.to_string(),
+                  };
+                  response_sender.send(SocketMessage::Error(frame)).ok();
+                }
+                continue;
               }
             };
 
-            match incoming_sender.send(message) {
-              Ok(_) => {}
-              Err(err) => eprintln!("Could not broadcast incoming socket message: {err}"),
+            match message {
+              SocketMessage::Request(request) => {
+                let request_handlers = request_handlers.clone();
+                let guard = guard.clone();
+                let metadata = metadata.clone();
+                let response_sender = response_sender.clone();
+                tokio::spawn(async move {
+                  let sent = Self::socket_request_handler(
+                    request_handlers,
+                    guard,
+                    metadata,
+                    request,
+                    response_sender,
+                  )
+                  .await;
+                  if let Err(err) = sent {
+                    eprintln!("Could not send socket response: {err}");
+                  }
+                });
+              }
+              SocketMessage::Notification(notification) => {
+                let request_handlers = request_handlers.clone();
+                let guard = guard.clone();
+                let mut metadata = metadata.clone();
+                tokio::spawn(async move {
+                  let identifier = notification.request.identifier;
+                  metadata.identifier = Some(identifier.clone());
+                  let context = match Self::authorize(&guard, nanoid::nanoid!(), metadata).await {
+                    Ok(v) => v,
+                    Err(err) => {
+                      eprintln!("Rejected notification {identifier}: {err}");
+                      return;
+                    }
+                  };
+                  match request_handlers
+                    .read()
+                    .await
+                    .get(&identifier)
+                    .map(|v| v.handler.clone())
+                  {
+                    Some(handler) => Self::spawn_notification(
+                      handler,
+                      notification.request.parameters,
+                      context,
+                      identifier,
+                    ),
+                    None => eprintln!("No handler registered for notification {identifier}"),
+                  }
+                });
+              }
+              SocketMessage::Subscribe(subscribe) => {
+                let guard = guard.clone();
+                let mut metadata = metadata.clone();
+                let pub_sub = pub_sub.clone();
+                let socket = reader_socket.clone();
+                tokio::spawn(async move {
+                  metadata.topic = Some(subscribe.subscribe.clone());
+                  match Self::authorize(&guard, nanoid::nanoid!(), metadata).await {
+                    Ok(_) => pub_sub.subscribe(&subscribe.subscribe, socket),
+                    Err(err) => {
+                      eprintln!("Rejected subscription to {}: {err}", subscribe.subscribe)
+                    }
+                  }
+                });
+              }
+              SocketMessage::Unsubscribe(unsubscribe) => {
+                pub_sub.unsubscribe(&unsubscribe.unsubscribe, &reader_socket.id);
+              }
+              SocketMessage::Response(response) => {
+                let sender = reader_socket
+                  .calls
+                  .lock()
+                  .ok()
+                  .and_then(|mut v| v.remove(&response.id));
+                let sent = match sender {
+                  // a response to a call of the server itself, e.g. a broadcast
+                  Some(sender) => {
+                    sender.send(response).ok();
+                    Ok(())
+                  }
+                  None => incoming_sender.send(SocketMessage::Response(response)),
+                };
+                if let Err(err) = sent {
+                  eprintln!("Could not broadcast incoming socket message: {err}");
+                }
+              }
+              SocketMessage::StreamItem(_)
+              | SocketMessage::StreamError(_)
+              | SocketMessage::StreamEnd(_) => match incoming_sender.send(message) {
+                Ok(_) => {}
+                Err(err) => eprintln!("Could not broadcast incoming socket message: {err}"),
+              },
+              SocketMessage::Ping(ping) => {
+                response_sender
+                  .send(SocketMessage::Pong(Pong { id: ping.id }))
+                  .ok();
+              }
+              SocketMessage::Pong(_) => {}
+              SocketMessage::Error(err) => {
+                eprintln!(
+                  "Socket {} reported a protocol error: {}",
+                  reader_socket.id, err.message
+                );
+                let call = err.id.as_ref().and_then(|id| {
+                  reader_socket
+                    .calls
+                    .lock()
+                    .ok()
+                    .and_then(|mut v| v.remove(id))
+                });
+                // dropping the sender fails a call of the server itself, others might belong to a target
+                if call.is_none() {
+                  incoming_sender.send(SocketMessage::Error(err)).ok();
+                }
+              }
             };
           }
+
+          match sockets.write() {
+            Ok(mut v) => {
+              if let Some(role_sockets) = v.get_mut(&reader_socket.role) {
+                role_sockets.remove(&reader_socket.id);
+                if role_sockets.is_empty() {
+                  v.remove(&reader_socket.role);
+                }
+              }
+            }
+            Err(err) => eprintln!("Could not unregister socket: {err}"),
+          }
+          pub_sub.unsubscribe_all(&reader_socket.id);
+          // dropping the senders fails the open calls
+          if let Ok(mut v) = reader_socket.calls.lock() {
+            v.clear();
+          }
+
+          reader_done_sender.send(()).ok();
+          reader_event_sender
+            .send(SocketEvent::Close {
+              code: close_frame.0,
+              reason: close_frame.1,
+            })
+            .ok();
         });
 
         tokio::spawn(async move {
           loop {
-            let message = match outgoing_reciever.recv_async().await {
-              Ok(v) => v,
-              Err(err) => {
-                eprintln!("Error while processing outgoing socket message: {err}");
+            let message = tokio::select! {
+              v = outgoing_reciever.recv_async() => match v {
+                Ok(v) => v,
+                // all handles to the socket have been dropped
+                Err(_) => break,
+              },
+              v = close_reciever.recv_async() => {
+                if let Ok((code, reason)) = v {
+                  let frame = warp::ws::Message::close_with(code, reason);
+                  if let Err(err) = socket_sender.send(frame).await {
+                    event_sender
+                      .send(SocketEvent::Error(format!("Could not close websocket: {err}")))
+                      .ok();
+                  }
+                }
                 break;
-              }
+              },
+              _ = &mut reader_done_reciever => break,
             };
 
-            let text = match serde_json::to_string(&message) {
-              Ok(v) => v,
+            let frame = match message.encode(encoding) {
+              Ok(Frame::Text(v)) => warp::ws::Message::text(v),
+              Ok(Frame::Binary(v)) => warp::ws::Message::binary(v),
               Err(err) => {
                 eprintln!("Could not serialize ws message: {err}");
-                return;
+                continue;
               }
             };
-            socket_sender
-              .send(warp::ws::Message::text(text))
-              .await
-              .unwrap();
+            if let Err(err) = socket_sender.send(frame).await {
+              eprintln!("Could not send ws message: {err}");
+              event_sender
+                .send(SocketEvent::Error(format!(
+                  "Could not send websocket message: {err}"
+                )))
+                .ok();
+              break;
+            }
           }
         });
 
-        socket_channel
-          .0
-          .send_async(Socket {
-            sender: outgoing_sender.clone(),
-            reciever: incoming_reciever.clone(),
-            role: role.clone(),
-          })
-          .await
-          .unwrap();
-      }))
+        let notified = socket_channel.0.send_async(socket).await;
+        if let Err(err) = notified {
+          eprintln!("Could not notify about new socket connection: {err}");
+          err
+            .into_inner()
+            .close(1011, "Internal server error".to_string())
+            .ok();
+        }
+      });
+
+      let upgrade = Self::with_version(upgrade, version);
+      match subprotocol {
+        // clients offering subprotocols expect the accepted one to be echoed
+        Some(v) => Box::new(warp::reply::with_header(
+          upgrade,
+          "sec-websocket-protocol",
+          v.name(),
+        )),
+        None => Box::new(upgrade),
+      }
     } else {
       Box::new(warp::reply::with_status(
         "Websockets are disabled",
@@ -1,17 +1,64 @@
-use super::{protocol::socket::SocketMessage, Socket};
+use super::{
+  client::{self, InFlightPolicy, ReconnectPolicy},
+  error::ERPCError,
+  protocol::{
+    encoding::{Encoding, StreamFormat},
+    socket::SocketMessage,
+    ApplicationError, BatchResult, Envelope, PROTOCOL_VERSION, VERSION_HEADER,
+  },
+  server::ResponseStream,
+  Socket,
+};
 use nanoid::nanoid;
 use serde::{de::DeserializeOwned, Serialize};
 use std::{
   collections::HashMap,
   fmt::Debug,
-  sync::{Arc, Mutex},
+  sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Mutex,
+  },
+  time::Duration,
 };
-use tokio::sync::oneshot;
+use tokio::sync::{oneshot, watch};
+
+type Requests = Mutex<HashMap<String, OpenRequest>>;
+
+/**
+  A request sent via socket which still waits for its response
+*/
+#[derive(Debug)]
+struct OpenRequest {
+  /**
+    The id of the socket the request has been sent over, None while it waits to be resent after a reconnect
+  */
+  socket_id: Option<String>,
+  sender: ResponseSender,
+  /**
+    The request to send again after a reconnect, see InFlightPolicy::Resend
+  */
+  resend: Option<crate::erpc::protocol::Request>,
+}
+
+/**
+  An item of a stream received via socket, None once the stream ended
+*/
+type StreamEvent = Result<Option<serde_json::Value>, ERPCError>;
+
+#[derive(Debug)]
+enum ResponseSender {
+  Value(oneshot::Sender<super::protocol::socket::Response>),
+  Stream(flume::Sender<StreamEvent>),
+}
 
 #[derive(Debug, Clone)]
 pub enum TargetType {
   HTTPServer,
   Browser,
+  /**
+    A server the target connects to via websocket itself, see ERPCTarget::connect
+  */
+  SocketServer,
 }
 
 //TODO find a better/faster way to store open requests
@@ -21,8 +68,59 @@ pub struct ERPCTarget {
   port: u16,
   target_type: TargetType,
   socket: Arc<Mutex<Option<Socket>>>,
-  requests: Arc<Mutex<HashMap<String, oneshot::Sender<super::protocol::socket::Response>>>>,
+  requests: Arc<Requests>,
   reqwest_client: reqwest::Client,
+  /**
+    The default time to wait for a response before failing a call
+  */
+  timeout: Option<Duration>,
+  /**
+    The encoding of requests to http servers
+  */
+  encoding: Encoding,
+  /**
+    The role socket server targets connect with
+  */
+  role: String,
+  reconnect: ReconnectPolicy,
+  /**
+    Set once a socket server target has been closed or gave up reconnecting
+  */
+  closed: Arc<watch::Sender<bool>>,
+  /**
+    Set once a socket server target has connected, calls are only kept for resending from then on
+  */
+  has_connected: Arc<AtomicBool>,
+  /**
+    Whether a socket is connected, to be notified about changes
+  */
+  connection: Arc<watch::Sender<bool>>,
+}
+
+/**
+  Converts the error ending a stream, see protocol::socket::StreamError
+*/
+fn stream_error(error: Result<ApplicationError, String>) -> ERPCError {
+  match error {
+    Ok(v) => ERPCError::Application(v),
+    Err(v) => ERPCError::HandlerFailed(v),
+  }
+}
+
+/**
+  Removes an open request from the requests map when the call finishes, times out or is cancelled
+*/
+struct PendingRequest {
+  id: String,
+  requests: Arc<Requests>,
+}
+
+impl Drop for PendingRequest {
+  fn drop(&mut self) {
+    if let Ok(mut requests) = self.requests.lock() {
+      requests.remove(&self.id);
+    }
+  }
 }
 
 impl ERPCTarget {
@@ -38,139 +136,813 @@ impl ERPCTarget {
       socket: Arc::new(Mutex::new(None::<Socket>)),
       requests: Arc::new(Mutex::new(HashMap::new())),
       reqwest_client: reqwest::Client::new(),
+      timeout: None,
+      encoding: Encoding::default(),
+      role: String::new(),
+      reconnect: ReconnectPolicy::default(),
+      closed: Arc::new(watch::channel(false).0),
+      has_connected: Arc::new(AtomicBool::new(false)),
+      connection: Arc::new(watch::channel(false).0),
     }
   }
 
+  /**
+    Sets the role socket server targets connect with, i.e. the server endpoint ws/{role}
+  */
+  pub fn set_role(&mut self, role: String) {
+    self.role = role;
+  }
+
+  /**
+    Sets how socket server targets reconnect and what happens to calls waiting for a response meanwhile
+  */
+  pub fn set_reconnect_policy(&mut self, policy: ReconnectPolicy) {
+    self.reconnect = policy;
+  }
+
+  /**
+    Sets the encoding requests to http servers are sent in. Responses are read in the encoding the server replies with.
+  */
+  pub fn set_encoding(&mut self, encoding: Encoding) {
+    self.encoding = encoding;
+  }
+
+  /**
+    Sets the default time to wait for a response before a call fails with a timeout error
+  */
+  pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+    self.timeout = timeout;
+  }
+
+  /**
+    The default time to wait for a response
+  */
+  pub fn timeout(&self) -> Option<Duration> {
+    self.timeout
+  }
+
+  #[allow(dead_code)]
   pub async fn call<P: Serialize, R: DeserializeOwned + Debug>(
     &self,
     identifier: String,
     parameters: Vec<P>,
-  ) -> Result<R, String> {
+  ) -> Result<R, ERPCError> {
+    self
+      .call_with_timeout(identifier, parameters, self.timeout)
+      .await
+  }
+
+  /**
+    Calls the handler and fails with a timeout error if there is no response within the given duration.
+    Dropping the returned future cancels the call.
+  */
+  pub async fn call_with_timeout<P: Serialize, R: DeserializeOwned + Debug>(
+    &self,
+    identifier: String,
+    parameters: Vec<P>,
+    timeout: Option<Duration>,
+  ) -> Result<R, ERPCError> {
+    let call = self.send_call(identifier, parameters);
+    match timeout {
+      Some(timeout) => tokio::time::timeout(timeout, call)
+        .await
+        .map_err(|_| ERPCError::Timeout)?,
+      None => call.await,
+    }
+  }
+
+  async fn send_call<P: Serialize, R: DeserializeOwned + Debug>(
+    &self,
+    identifier: String,
+    parameters: Vec<P>,
+  ) -> Result<R, ERPCError> {
     // making sure that the protocol::Request is used to break this if the protocol should ever change
     let request = crate::erpc::protocol::Request {
       identifier,
       parameters: parameters
         .iter()
         .map(serde_json::to_value)
-        .collect::<Result<Vec<_>, _>>()
-        .map_err(|err| format!("Could not parse parameters: {err}"))?,
+        .collect::<Result<Vec<_>, _>>()?,
     };
 
     match self.target_type {
       TargetType::HTTPServer => {
+        let identifier = request.identifier.clone();
         let r = self
           .reqwest_client
           .post(format!(
             "{}:{}/handlers/{}",
-            self.address, self.port, request.identifier
+            self.address, self.port, identifier
           ))
-          .header("Content-Type", "application/json")
-          .body(serde_json::to_vec(&request.parameters).expect("Vec of json::Value should be ok"));
+          .header("Content-Type", self.encoding.content_type())
+          .header(VERSION_HEADER, PROTOCOL_VERSION)
+          .header("Accept", self.encoding.content_type())
+          .body(
+            self
+              .encoding
+              .serialize(&Envelope::new(request, nanoid!()))?,
+          );
 
-        let response = r
-          .send()
-          .await
-          .map_err(|err| format!("Request errored: {err}"))?
-          .bytes()
-          .await
-          .map_err(|err| format!("Error while awaiting request body: {err}"))?;
-
-        serde_json::from_slice(&response)
-          .map_err(|err| format!("Could not deserialize response: {err}"))
-      }
-      TargetType::Browser => {
-        let socket = {
-          let socket = self
-            .socket
-            .lock()
-            .map_err(|err| format!("Could not lock socket mutex: {err}"))?;
-
-          match &*socket {
-            Some(v) => v.clone(),
-            None => return Err("Socket not set for this target".to_string()),
-          }
-        };
+        let response = r.send().await?;
+        let status = response.status();
+        let encoding = response
+          .headers()
+          .get(reqwest::header::CONTENT_TYPE)
+          .and_then(|v| v.to_str().ok())
+          .and_then(Encoding::from_content_type);
+        if let (reqwest::StatusCode::UNPROCESSABLE_ENTITY, Some(encoding)) = (status, encoding) {
+          let body = response.bytes().await?;
+          return match encoding.deserialize::<ApplicationError>(&body) {
+            Ok(err) => Err(ERPCError::Application(err)),
+            Err(_) => Err(ERPCError::HandlerFailed(
+              String::from_utf8_lossy(&body).into_owned(),
+            )),
+          };
+        }
+        if !status.is_success() {
+          return Err(ERPCError::from_status(
+            status,
+            &identifier,
+            response.text().await?,
+          ));
+        }
 
-        let id = nanoid!();
+        encoding
+          .unwrap_or_default()
+          .deserialize(&response.bytes().await?)
+      }
+      TargetType::Browser | TargetType::SocketServer => {
         let (sender, reciever) = oneshot::channel::<super::protocol::socket::Response>();
-        {
-          // scope to drop the requests lock
-          let mut requests = self
-            .requests
-            .lock()
-            .map_err(|err| format!("Could not access sockets: {err}"))?;
+        let _pending = self.send_socket_request(request, ResponseSender::Value(sender))?;
 
-          requests.insert(id.clone(), sender);
+        let response = reciever.await.map_err(|_| ERPCError::SocketClosed)?;
+        let response = response.body.map_err(ERPCError::HandlerFailed)?;
+        if let Some(err) = response.error {
+          return Err(ERPCError::Application(err));
         }
 
+        Ok(serde_json::from_value(response.body)?)
+      }
+    }
+  }
+
+  /**
+    Makes several calls at once and returns their results in the same order.
+    Http server targets send them as a single batch request, socket targets send them concurrently.
+    Fails as a whole when the batch request itself fails or exceeds the timeout of the target.
+  */
+  pub async fn call_batch(
+    &self,
+    requests: Vec<crate::erpc::protocol::Request>,
+  ) -> Result<Vec<Result<serde_json::Value, ERPCError>>, ERPCError> {
+    let batch = self.send_batch(requests);
+    match self.timeout {
+      Some(timeout) => tokio::time::timeout(timeout, batch)
+        .await
+        .map_err(|_| ERPCError::Timeout)?,
+      None => batch.await,
+    }
+  }
+
+  async fn send_batch(
+    &self,
+    requests: Vec<crate::erpc::protocol::Request>,
+  ) -> Result<Vec<Result<serde_json::Value, ERPCError>>, ERPCError> {
+    match self.target_type {
+      TargetType::HTTPServer => {
+        let response = self
+          .reqwest_client
+          .post(format!("{}:{}/batch", self.address, self.port))
+          .header("Content-Type", self.encoding.content_type())
+          .header(VERSION_HEADER, PROTOCOL_VERSION)
+          .header("Accept", self.encoding.content_type())
+          .body(
+            self.encoding.serialize(
+              &requests
+                .iter()
+                .map(|v| Envelope::new(v.clone(), nanoid!()))
+                .collect::<Vec<_>>(),
+            )?,
+          )
+          .send()
+          .await?;
+
+        let status = response.status();
+        if !status.is_success() {
+          return Err(ERPCError::from_status(
+            status,
+            "batch",
+            response.text().await?,
+          ));
+        }
+        let encoding = response
+          .headers()
+          .get(reqwest::header::CONTENT_TYPE)
+          .and_then(|v| v.to_str().ok())
+          .and_then(Encoding::from_content_type)
+          .unwrap_or_default();
+        let results: Vec<BatchResult> = encoding.deserialize(&response.bytes().await?)?;
+        if results.len() != requests.len() {
+          return Err(ERPCError::Serialization(format!(
+            "Expected {} batch results, got {}",
+            requests.len(),
+            results.len()
+          )));
+        }
+
+        Ok(
+          requests
+            .iter()
+            .zip(results)
+            .map(|(request, result)| {
+              let status = reqwest::StatusCode::from_u16(result.status)
+                .unwrap_or(reqwest::StatusCode::BAD_GATEWAY);
+              if status.is_success() {
+                return Ok(result.body);
+              }
+              let message = match result.body {
+                serde_json::Value::String(v) => v,
+                v => v.to_string(),
+              };
+              Err(ERPCError::from_status(status, &request.identifier, message))
+            })
+            .collect(),
+        )
+      }
+      TargetType::Browser | TargetType::SocketServer => {
+        let calls = requests.into_iter().map(|request| {
+          self.send_call::<_, serde_json::Value>(request.identifier, request.parameters)
+        });
+        Ok(futures_util::future::join_all(calls).await)
+      }
+    }
+  }
+
+  /**
+    Sends a notification, which the receiver handles without responding.
+    Via http, this resolves once the server accepted the notification, via socket once it has been queued for sending.
+  */
+  pub async fn notify<P: Serialize>(
+    &self,
+    identifier: String,
+    parameters: Vec<P>,
+  ) -> Result<(), ERPCError> {
+    let request = crate::erpc::protocol::Request {
+      identifier,
+      parameters: parameters
+        .iter()
+        .map(serde_json::to_value)
+        .collect::<Result<Vec<_>, _>>()?,
+    };
+
+    match self.target_type {
+      TargetType::HTTPServer => {
+        let identifier = request.identifier.clone();
+        let response = self
+          .reqwest_client
+          .post(format!(
+            "{}:{}/notifications/{}",
+            self.address, self.port, identifier
+          ))
+          .header("Content-Type", self.encoding.content_type())
+          .header(VERSION_HEADER, PROTOCOL_VERSION)
+          .body(
+            self
+              .encoding
+              .serialize(&Envelope::new(request, nanoid!()))?,
+          )
+          .send()
+          .await?;
+
+        let status = response.status();
+        if !status.is_success() {
+          return Err(ERPCError::from_status(
+            status,
+            &identifier,
+            response.text().await?,
+          ));
+        }
+        Ok(())
+      }
+      TargetType::Browser | TargetType::SocketServer => {
+        let socket = self.connected_socket()?;
         socket
           .sender
-          .send(SocketMessage::Request(super::protocol::socket::Request {
-            id,
-            request,
-          }))
-          .unwrap();
+          .send(SocketMessage::Notification(
+            super::protocol::socket::Notification { request },
+          ))
+          .map_err(|_| ERPCError::SocketClosed)
+      }
+    }
+  }
 
-        let response = reciever
-          .await
-          .map_err(|err| format!("RecvError in socket response channel: {err}"))?;
+  /**
+    Calls a handler which streams its response. Handlers responding with a single value yield a single item.
+    Dropping the returned stream cancels the call.
+  */
+  pub async fn stream<P: Serialize>(
+    &self,
+    identifier: String,
+    parameters: Vec<P>,
+  ) -> Result<ResponseStream, ERPCError> {
+    let request = crate::erpc::protocol::Request {
+      identifier,
+      parameters: parameters
+        .iter()
+        .map(serde_json::to_value)
+        .collect::<Result<Vec<_>, _>>()?,
+    };
 
-        let response = response.body?;
+    match self.target_type {
+      TargetType::HTTPServer => {
+        let identifier = request.identifier.clone();
+        let response = self
+          .reqwest_client
+          .post(format!(
+            "{}:{}/handlers/{}",
+            self.address, self.port, identifier
+          ))
+          .header("Content-Type", self.encoding.content_type())
+          .header(VERSION_HEADER, PROTOCOL_VERSION)
+          .header("Accept", StreamFormat::Ndjson.content_type())
+          .body(
+            self
+              .encoding
+              .serialize(&Envelope::new(request, nanoid!()))?,
+          )
+          .send()
+          .await?;
+
+        let status = response.status();
+        if !status.is_success() {
+          return Err(ERPCError::from_status(
+            status,
+            &identifier,
+            response.text().await?,
+          ));
+        }
+
+        Ok(Box::pin(futures_util::stream::unfold(
+          Some((response, Vec::new())),
+          |state| async move {
+            let (mut response, mut buffer) = state?;
+            loop {
+              if let Some(position) = buffer.iter().position(|v| *v == b'\n') {
+                let line: Vec<u8> = buffer.drain(..=position).collect();
+                if line.iter().all(|v| v.is_ascii_whitespace()) {
+                  continue;
+                }
+                return match Self::stream_event(&line) {
+                  Ok(Some(v)) => Some((Ok(v), Some((response, buffer)))),
+                  Ok(None) => None,
+                  Err(err) => Some((Err(err), None)),
+                };
+              }
 
-        serde_json::from_value(response.body)
-          .map_err(|err| format!("Could not parse socket response: {err}"))?
+              match response.chunk().await {
+                Ok(Some(chunk)) => buffer.extend_from_slice(&chunk),
+                Ok(None) => {
+                  return Some((
+                    Err(ERPCError::Transport(
+                      "The stream ended unexpectedly".to_string(),
+                    )),
+                    None,
+                  ))
+                }
+                Err(err) => return Some((Err(err.into()), None)),
+              }
+            }
+          },
+        )))
       }
+      TargetType::Browser | TargetType::SocketServer => {
+        let (sender, reciever) = flume::unbounded::<StreamEvent>();
+        let pending = self.send_socket_request(request, ResponseSender::Stream(sender))?;
+
+        Ok(Box::pin(futures_util::stream::unfold(
+          Some((reciever, pending)),
+          |state| async move {
+            let (reciever, pending) = state?;
+            match reciever.recv_async().await {
+              Ok(Ok(Some(v))) => Some((Ok(v), Some((reciever, pending)))),
+              Ok(Ok(None)) => None,
+              Ok(Err(err)) => Some((Err(err), None)),
+              // the socket disconnected before the stream ended
+              Err(_) => Some((Err(ERPCError::SocketClosed), None)),
+            }
+          },
+        )))
+      }
+    }
+  }
+
+  /**
+    Reads a line of a newline delimited json stream
+  */
+  fn stream_event(line: &[u8]) -> StreamEvent {
+    match serde_json::from_slice(line)? {
+      SocketMessage::StreamItem(v) => Ok(Some(v.item)),
+      SocketMessage::StreamEnd(_) => Ok(None),
+      SocketMessage::StreamError(v) => Err(stream_error(v.error)),
+      _ => Err(ERPCError::Serialization(
+        "Expected a stream message".to_string(),
+      )),
+    }
+  }
+
+  fn connected_socket(&self) -> Result<Socket, ERPCError> {
+    let socket = self
+      .socket
+      .lock()
+      .map_err(|err| ERPCError::Internal(format!("Could not lock socket mutex: {err}")))?;
+
+    match &*socket {
+      Some(v) => Ok(v.clone()),
+      None => Err(ERPCError::SocketClosed),
+    }
+  }
+
+  /**
+    Whether calls are kept and resent when the connection drops, see InFlightPolicy::Resend
+  */
+  fn resends(&self) -> bool {
+    matches!(self.target_type, TargetType::SocketServer)
+      && self.reconnect.in_flight == InFlightPolicy::Resend
+      && self.has_connected.load(Ordering::SeqCst)
+      && !*self.closed.borrow()
+  }
+
+  /**
+    Sends a request via the connected socket and registers where its response should go.
+    When the target resends calls, value requests made while reconnecting are queued instead of failing.
+    The request stays open until the returned guard is dropped.
+  */
+  fn send_socket_request(
+    &self,
+    request: crate::erpc::protocol::Request,
+    sender: ResponseSender,
+  ) -> Result<PendingRequest, ERPCError> {
+    let id = nanoid!();
+    let resend = match sender {
+      ResponseSender::Value(_) if self.resends() => Some(request.clone()),
+      _ => None,
+    };
+
+    // the requests are locked before the socket, so a reconnect can not miss a queued request
+    let mut requests = self
+      .requests
+      .lock()
+      .map_err(|err| ERPCError::Internal(format!("Could not access sockets: {err}")))?;
+    let socket = match self.connected_socket() {
+      Ok(v) => Some(v),
+      Err(_) if resend.is_some() => None,
+      Err(err) => return Err(err),
+    };
+
+    requests.insert(
+      id.clone(),
+      OpenRequest {
+        socket_id: socket.as_ref().map(|v| v.id.clone()),
+        sender,
+        resend,
+      },
+    );
+    drop(requests);
+    let pending = PendingRequest {
+      id: id.clone(),
+      requests: self.requests.clone(),
+    };
+
+    if let Some(socket) = socket {
+      socket
+        .sender
+        .send(SocketMessage::Request(super::protocol::socket::Request {
+          id,
+          request,
+        }))
+        .map_err(|_| ERPCError::SocketClosed)?;
     }
+
+    Ok(pending)
+  }
+
+  /**
+    The number of calls over sockets waiting for a response
+  */
+  #[allow(dead_code)]
+  pub fn open_requests(&self) -> usize {
+    self.requests.lock().map(|v| v.len()).unwrap_or(0)
   }
 
+  /**
+    Whether a socket is currently connected to this target
+  */
+  pub fn is_connected(&self) -> bool {
+    self.socket.lock().map(|v| v.is_some()).unwrap_or(false)
+  }
+
+  /**
+    The protocol version negotiated with the peer of the connected socket
+  */
+  pub fn protocol_version(&self) -> Option<u32> {
+    self.connected_socket().ok().map(|v| v.version)
+  }
+
+  /**
+    Notifies about the target connecting and disconnecting, e.g. when a socket server target reconnects
+  */
+  pub fn watch_connection(&self) -> watch::Receiver<bool> {
+    self.connection.subscribe()
+  }
+
+  /**
+    Handles responses arriving via the socket until it disconnects.
+    On disconnect, all calls still waiting for a response via this socket fail with a socket closed error.
+  */
   pub async fn listen_on_socket(&mut self, socket: Socket) {
-    match self.socket.lock() {
-      Ok(mut v) => {
-        *v = Some(socket.clone());
-      }
-      Err(err) => {
-        eprintln!("Socket lock error: {err}");
-        return;
-      }
+    if let Err(err) = self.set_socket(&socket) {
+      eprintln!("{err}");
+      return;
     }
 
     loop {
       let msg = match socket.reciever.recv_async().await {
         Ok(v) => v,
-        Err(err) => {
-          eprintln!("Socket stream error: {err}");
-          return;
-        }
+        Err(_) => break,
       };
 
       match msg {
-        SocketMessage::Request(_) => {
-          eprintln!("Requests via websocket not supported yet!");
-          return;
+        SocketMessage::Request(req) => {
+          // requests from the socket are answered by the server directly and never reach the target
+          eprintln!("Ignoring socket request {} on target", req.id);
+        }
+        SocketMessage::Notification(notification) => {
+          eprintln!(
+            "Ignoring socket notification {} on target",
+            notification.request.identifier
+          );
+        }
+        SocketMessage::Subscribe(_) | SocketMessage::Unsubscribe(_) => {
+          // subscriptions are handled by the server directly and never reach the target
+          eprintln!("Ignoring socket subscription on target");
         }
         SocketMessage::Response(res) => {
-          let mut requests = match self.requests.lock() {
+          let id = res.id.clone();
+          match self.take_open_request(&id) {
+            Some(ResponseSender::Value(sender)) => {
+              if let Err(res) = sender.send(res) {
+                eprintln!("Could not send response for {}", res.id);
+              }
+            }
+            // a handler responding with a single value to a stream call
+            Some(ResponseSender::Stream(sender)) => {
+              let event = match res.body {
+                Ok(v) => match v.error {
+                  Some(err) => Err(ERPCError::Application(err)),
+                  None => Ok(Some(v.body)),
+                },
+                Err(err) => Err(ERPCError::HandlerFailed(err)),
+              };
+              let ended = event.is_err();
+              sender.send(event).ok();
+              if !ended {
+                sender.send(Ok(None)).ok();
+              }
+            }
+            None => eprintln!("Could not find open request for id {id}"),
+          }
+        }
+        SocketMessage::StreamItem(item) => {
+          let requests = match self.requests.lock() {
             Ok(v) => v,
             Err(err) => {
               eprintln!("Could not access requests (1): {err}");
-              return;
+              break;
             }
           };
 
-          let return_channel = match requests.remove(&res.id) {
-            Some(v) => v,
-            None => {
-              eprintln!("Could not find open request for id {}", res.id);
-              return;
+          match requests.get(&item.id).map(|v| &v.sender) {
+            Some(ResponseSender::Stream(sender)) => {
+              sender.send(Ok(Some(item.item))).ok();
             }
-          };
-
-          match return_channel.send(res) {
-            Ok(_) => {}
-            Err(ret_res) => eprintln!("Could not send response for {}", ret_res.id),
-          };
+            _ => eprintln!("Could not find open stream for id {}", item.id),
+          }
+        }
+        SocketMessage::StreamEnd(end) => match self.take_open_request(&end.id) {
+          Some(ResponseSender::Stream(sender)) => {
+            sender.send(Ok(None)).ok();
+          }
+          _ => eprintln!("Could not find open stream for id {}", end.id),
+        },
+        SocketMessage::Ping(_) | SocketMessage::Pong(_) => {
+          // pings are answered by the server or the socket connection directly
         }
+        SocketMessage::Error(err) => {
+          eprintln!("Protocol error reported via socket: {}", err.message);
+          let message = format!("Protocol error: {}", err.message);
+          match err
+            .id
+            .and_then(|id| self.take_open_request(&id).map(|v| (id, v)))
+          {
+            Some((id, ResponseSender::Value(sender))) => {
+              sender
+                .send(super::protocol::socket::Response {
+                  id,
+                  body: Err(message),
+                })
+                .ok();
+            }
+            Some((_, ResponseSender::Stream(sender))) => {
+              sender.send(Err(ERPCError::HandlerFailed(message))).ok();
+            }
+            None => {}
+          }
+        }
+        SocketMessage::StreamError(err) => match self.take_open_request(&err.id) {
+          Some(ResponseSender::Stream(sender)) => {
+            sender.send(Err(stream_error(err.error))).ok();
+          }
+          _ => eprintln!("Could not find open stream for id {}", err.id),
+        },
+      };
+    }
+
+    self.disconnect(&socket);
+  }
+
+  /**
+    Removes an open request to hand it its response.
+    The request might have timed out or been cancelled in the meantime.
+  */
+  fn take_open_request(&self, id: &str) -> Option<ResponseSender> {
+    match self.requests.lock() {
+      Ok(mut requests) => requests.remove(id).map(|v| v.sender),
+      Err(err) => {
+        eprintln!("Could not access requests (1): {err}");
+        None
+      }
+    }
+  }
+
+  /**
+    Sets the socket calls are sent over and sends the requests which wait to be resent
+  */
+  fn set_socket(&self, socket: &Socket) -> Result<(), ERPCError> {
+    let mut requests = self
+      .requests
+      .lock()
+      .map_err(|err| ERPCError::Internal(format!("Could not access requests: {err}")))?;
+    *self
+      .socket
+      .lock()
+      .map_err(|err| ERPCError::Internal(format!("Socket lock error: {err}")))? =
+      Some(socket.clone());
+    self.connection.send_replace(true);
+
+    for (id, open_request) in requests.iter_mut() {
+      if let (None, Some(request)) = (&open_request.socket_id, &open_request.resend) {
+        open_request.socket_id = Some(socket.id.clone());
+        socket
+          .sender
+          .send(SocketMessage::Request(super::protocol::socket::Request {
+            id: id.clone(),
+            request: request.clone(),
+          }))
+          .ok();
+      }
+    }
+    Ok(())
+  }
+
+  /**
+    Connects to the ws/{role} endpoint of a socket server target, retrying according to the reconnect policy.
+    Once connected, the target keeps reconnecting in the background whenever the connection drops,
+    until it is closed or gives up. Fails when the server rejects the connection or all attempts failed.
+  */
+  pub async fn connect(&self) -> Result<(), ERPCError> {
+    if !matches!(self.target_type, TargetType::SocketServer) {
+      return Err(ERPCError::Internal(
+        "Only socket server targets can connect".to_string(),
+      ));
+    }
+    if self.is_connected() {
+      return Ok(());
+    }
+    // fails right away instead of retrying addresses which can never be dialed
+    client::websocket_address(&self.address)?;
+    self.closed.send_replace(false);
+
+    let socket = match self.dial().await {
+      Ok(v) => v,
+      Err(err) => {
+        self.give_up();
+        return Err(err);
+      }
+    };
+    // set right away, so calls can be made once connected
+    self.set_socket(&socket)?;
+    self.has_connected.store(true, Ordering::SeqCst);
+
+    let mut target = self.clone();
+    tokio::spawn(async move {
+      let mut socket = socket;
+      loop {
+        target.listen_on_socket(socket).await;
+        socket = match target.dial().await {
+          Ok(v) => v,
+          Err(err) => {
+            if !*target.closed.borrow() {
+              eprintln!("Gave up reconnecting to {}: {err}", target.address);
+            }
+            target.give_up();
+            return;
+          }
+        };
+      }
+    });
+    Ok(())
+  }
+
+  /**
+    Dials the server until connected, waiting between attempts as the reconnect policy says.
+    Rejected handshakes are not retried.
+  */
+  async fn dial(&self) -> Result<Socket, ERPCError> {
+    let mut closed = self.closed.subscribe();
+    let mut attempt = 0;
+    loop {
+      if *closed.borrow() {
+        return Err(ERPCError::SocketClosed);
+      }
+
+      let err = match client::dial(&self.address, self.port, &self.role, self.encoding).await {
+        Ok(v) => return Ok(v),
+        Err(err @ (ERPCError::Unauthorized(_) | ERPCError::Forbidden(_))) => return Err(err),
+        Err(err) => err,
       };
+      if !self.reconnect.should_retry(attempt + 1) {
+        return Err(err);
+      }
+
+      tokio::select! {
+        _ = tokio::time::sleep(self.reconnect.delay(attempt)) => {},
+        _ = closed.changed() => return Err(ERPCError::SocketClosed),
+      }
+      attempt += 1;
+    }
+  }
+
+  /**
+    Stops reconnecting and fails the calls waiting to be resent
+  */
+  fn give_up(&self) {
+    self.closed.send_replace(true);
+    match self.requests.lock() {
+      Ok(mut requests) => requests.retain(|_, v| v.socket_id.is_some()),
+      Err(err) => eprintln!("Could not access requests (3): {err}"),
+    }
+  }
+
+  /**
+    Closes the connection of a socket server target and stops reconnecting
+  */
+  pub fn close(&self) -> Result<(), ERPCError> {
+    self.closed.send_replace(true);
+    match self.connected_socket() {
+      Ok(socket) => socket.close(1000, String::new()),
+      Err(_) => {
+        self.give_up();
+        Ok(())
+      }
+    }
+  }
+
+  fn disconnect(&self, socket: &Socket) {
+    match self.socket.lock() {
+      Ok(mut v) => {
+        // a new socket might have been set in the meantime
+        if v.as_ref().map(|v| v.id == socket.id).unwrap_or(false) {
+          *v = None;
+          self.connection.send_replace(false);
+        }
+      }
+      Err(err) => eprintln!("Socket lock error: {err}"),
+    }
+
+    let resends = self.resends();
+    match self.requests.lock() {
+      // dropping the senders fails the open calls
+      Ok(mut requests) => requests.retain(|_, v| {
+        if v.socket_id.as_deref() != Some(&socket.id) {
+          return true;
+        }
+        if resends && v.resend.is_some() {
+          v.socket_id = None;
+          return true;
+        }
+        false
+      }),
+      Err(err) => eprintln!("Could not access requests (2): {err}"),
     }
   }
 }
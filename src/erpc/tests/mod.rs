@@ -1 +1,4 @@
-mod server;
\ No newline at end of file
+mod server;
+mod target;
+#[cfg(test)]
+mod util;
@@ -1,24 +1,1097 @@
 #[cfg(test)]
 mod tests {
-  use std::time::Duration;
+  use std::{collections::HashMap, sync::Arc, time::Duration};
 
+  use futures_util::{SinkExt, StreamExt};
   use tokio::time::sleep;
+  use tokio_tungstenite::tungstenite::{client::IntoClientRequest, Message};
 
-  use crate::erpc::server::ERPCServer;
+  use crate::erpc::{
+    context::RequestContext,
+    error::ERPCError,
+    protocol::{
+      binary::{self, Binary},
+      encoding::Encoding,
+      socket::TAGGED_VERSION,
+      HandlerMetadata, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION, VERSION_HEADER,
+    },
+    pubsub::LocalPubSub,
+    server::{ERPCServer, HandlerResponse},
+    target::{ERPCTarget, TargetType},
+    tests::util::{free_port, serve, start},
+    SocketEvent,
+  };
 
   #[test]
   fn creation() {
-    ERPCServer::new(5678, vec!["http://localhost".to_string()], true);
+    ERPCServer::new(0, vec!["http://localhost".to_string()], true);
   }
 
   #[tokio::test]
   async fn run_stop() {
-    let server = ERPCServer::new(5678, vec!["http://localhost".to_string()], true);
-    let s2 = server.clone();
-    tokio::spawn(async move {
-      sleep(Duration::from_millis(3000)).await;
-      s2.stop().unwrap();
+    let server = ERPCServer::new(0, vec!["http://localhost".to_string()], true);
+    let (_, fut) = server.run().unwrap();
+    server.stop().unwrap();
+    fut.await;
+  }
+
+  #[test]
+  fn socket_request() {
+    let mut server = ERPCServer::new(0, vec!["http://localhost".to_string()], true);
+    server.register_handler(|a: i32, b: i32| async move { a + b }, "add");
+
+    serve(server, |_, port| async move {
+      let (mut socket, _) =
+        tokio_tungstenite::connect_async(format!("ws://127.0.0.1:{port}/ws/Frontend"))
+          .await
+          .unwrap();
+
+      socket
+        .send(Message::text(
+          r#"{"id":"1","request":{"identifier":"add","parameters":[1,2]}}"#,
+        ))
+        .await
+        .unwrap();
+      let response: serde_json::Value =
+        serde_json::from_str(&socket.next().await.unwrap().unwrap().into_text().unwrap()).unwrap();
+      assert_eq!(response["id"], "1");
+      assert_eq!(response["body"]["Ok"]["body"], 3);
+
+      socket
+        .send(Message::text(
+          r#"{"id":"2","request":{"identifier":"unknown","parameters":[]}}"#,
+        ))
+        .await
+        .unwrap();
+      let response: serde_json::Value =
+        serde_json::from_str(&socket.next().await.unwrap().unwrap().into_text().unwrap()).unwrap();
+      assert_eq!(response["id"], "2");
+      assert!(response["body"]["Err"].is_string());
+    });
+  }
+
+  #[tokio::test]
+  async fn socket_close() {
+    let server = ERPCServer::new(0, vec!["http://localhost".to_string()], true);
+    let notifier = server.get_socket_notifier().clone();
+    let port = start(&server);
+
+    let (mut client, _) =
+      tokio_tungstenite::connect_async(format!("ws://127.0.0.1:{port}/ws/Frontend"))
+        .await
+        .unwrap();
+    let socket = notifier.recv_async().await.unwrap();
+    assert_eq!(socket.role, "Frontend");
+
+    socket.close(4000, "bye".to_string()).unwrap();
+    let message = client.next().await.unwrap().unwrap();
+    match message {
+      Message::Close(Some(frame)) => {
+        assert_eq!(u16::from(frame.code), 4000);
+        assert_eq!(frame.reason, "bye");
+      }
+      v => panic!("Expected close frame, got {v:?}"),
+    }
+    while client.next().await.is_some() {}
+
+    match socket.events.recv_async().await.unwrap() {
+      SocketEvent::Close { code, reason } => {
+        assert_eq!(code, Some(4000));
+        assert_eq!(reason, "bye");
+      }
+      v => panic!("Expected close event, got {v:?}"),
+    }
+    server.stop().unwrap();
+  }
+
+  #[test]
+  fn bind_addresses() {
+    let port = free_port();
+    let mut server = ERPCServer::new(0, vec!["http://localhost".to_string()], false);
+    server.set_addresses(vec![
+      format!("0.0.0.0:{port}").parse().unwrap(),
+      format!("[::]:{port}").parse().unwrap(),
+      "127.0.0.1:0".parse().unwrap(),
+    ]);
+    let mut dual_stack = ERPCServer::new(0, vec!["http://localhost".to_string()], false);
+    dual_stack.set_addresses(vec!["[::]:0".parse().unwrap()]);
+
+    tokio::runtime::Runtime::new()
+      .unwrap()
+      .block_on(async move {
+        let (addresses, fut) = server.run().unwrap();
+        assert_eq!(addresses.len(), 3);
+        assert_eq!(addresses[0].port(), port);
+        assert!(addresses[1].is_ipv6());
+        assert_ne!(addresses[2].port(), 0);
+
+        // without an IPv4 address of its own, IPv4 connections are accepted by the IPv6 socket
+        let dual_stack_port = start(&dual_stack);
+        assert!(
+          tokio::net::TcpStream::connect(("127.0.0.1", dual_stack_port))
+            .await
+            .is_ok()
+        );
+
+        dual_stack.stop().unwrap();
+        server.stop().unwrap();
+        fut.await;
+      });
+  }
+
+  #[test]
+  fn tls() {
+    let certificate = include_bytes!("fixtures/cert.pem");
+    let key = include_bytes!("fixtures/key.pem");
+    let reloaded_certificate = include_bytes!("fixtures/reloaded_cert.pem");
+    let reloaded_key = include_bytes!("fixtures/reloaded_key.pem");
+
+    let mut server = ERPCServer::new(0, vec!["http://localhost".to_string()], false);
+    server.register_handler(|a: i32, b: i32| async move { a + b }, "add");
+    server.set_tls(certificate, key).unwrap();
+    assert!(matches!(
+      ERPCServer::new(0, vec![], false).set_tls(b"invalid", key),
+      Err(ERPCError::Tls(_))
+    ));
+
+    serve(server, |server, port| async move {
+      // trusts only the given certificate and opens a new connection for every call
+      let call = |certificate: &'static [u8]| async move {
+        reqwest::Client::builder()
+          .tls_built_in_root_certs(false)
+          .add_root_certificate(reqwest::Certificate::from_pem(certificate).unwrap())
+          .pool_max_idle_per_host(0)
+          .build()
+          .unwrap()
+          .post(format!("https://localhost:{port}/handlers/add"))
+          .header("Content-Type", "application/json")
+          .body("[1,2]")
+          .send()
+          .await
+      };
+
+      assert_eq!(call(certificate).await.unwrap().text().await.unwrap(), "3");
+      assert!(call(reloaded_certificate).await.is_err());
+
+      server
+        .reload_tls(reloaded_certificate, reloaded_key)
+        .unwrap();
+      assert!(matches!(
+        server.reload_tls(b"invalid", key),
+        Err(ERPCError::Tls(_))
+      ));
+      assert_eq!(
+        call(reloaded_certificate)
+          .await
+          .unwrap()
+          .text()
+          .await
+          .unwrap(),
+        "3"
+      );
+      assert!(call(certificate).await.is_err());
+    });
+  }
+
+  #[test]
+  fn guard() {
+    let mut server = ERPCServer::new(0, vec!["http://localhost".to_string()], true);
+    server.register_raw_handler(
+      Box::new(|_, context| {
+        Box::pin(async move {
+          Ok(HandlerResponse::Value(serde_json::json!({
+            "auth": context.auth,
+            "remote": context.metadata.remote_address.is_some(),
+          })))
+        })
+      }),
+      "whoami",
+    );
+    server.set_guard(Box::new(|metadata| {
+      Box::pin(async move {
+        if metadata.headers.get("authorization").map(|v| v.as_str()) != Some("Bearer secret") {
+          return Err(ERPCError::Unauthorized("Missing token".to_string()));
+        }
+        if metadata.identifier.as_deref() == Some("admin") {
+          return Err(ERPCError::Forbidden("Admins only".to_string()));
+        }
+        Ok(Some(serde_json::json!({ "user": "alice" })))
+      })
+    }));
+
+    serve(server, |_, port| async move {
+      let client = reqwest::Client::new();
+      let call = |identifier: &'static str, token: Option<&'static str>| {
+        let mut request = client
+          .post(format!("http://127.0.0.1:{port}/handlers/{identifier}"))
+          .header("Content-Type", "application/json")
+          .body("[]");
+        if let Some(token) = token {
+          request = request.header("Authorization", token);
+        }
+        request.send()
+      };
+
+      let response = call("whoami", None).await.unwrap();
+      assert_eq!(response.status(), 401);
+      assert_eq!(response.text().await.unwrap(), "Missing token");
+
+      let response = call("admin", Some("Bearer secret")).await.unwrap();
+      assert_eq!(response.status(), 403);
+
+      let response = call("whoami", Some("Bearer secret"))
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+      assert_eq!(
+        serde_json::from_str::<serde_json::Value>(&response).unwrap(),
+        serde_json::json!({ "auth": { "user": "alice" }, "remote": true })
+      );
+
+      let result = ERPCTarget::new("http://127.0.0.1".to_string(), port, TargetType::HTTPServer)
+        .call::<(), serde_json::Value>("whoami".to_string(), vec![])
+        .await;
+      assert!(matches!(result, Err(ERPCError::Unauthorized(v)) if v == "Missing token"));
+
+      assert!(
+        tokio_tungstenite::connect_async(format!("ws://127.0.0.1:{port}/ws/Frontend"))
+          .await
+          .is_err()
+      );
+    });
+  }
+
+  #[test]
+  fn request_context() {
+    let mut server = ERPCServer::new(0, vec!["http://localhost".to_string()], false);
+    server.register_handler(
+      |context: RequestContext, name: String, greeting: Option<String>| async move {
+        context.set_cookie("session=abc; HttpOnly");
+        format!(
+          "{} {name} from {}",
+          greeting.unwrap_or("Hello".to_string()),
+          context.metadata.headers["user-agent"]
+        )
+      },
+      "greet",
+    );
+    server.register_handler(|| async { 42 }, "answer");
+
+    serve(server, |_, port| async move {
+      let client = reqwest::Client::new();
+
+      let response = client
+        .post(format!("http://127.0.0.1:{port}/handlers/greet"))
+        .header("Content-Type", "application/json")
+        .header("User-Agent", "tests")
+        .body(r#"["alice"]"#)
+        .send()
+        .await
+        .unwrap();
+      assert_eq!(response.headers()["set-cookie"], "session=abc; HttpOnly");
+      assert_eq!(
+        response.text().await.unwrap(),
+        r#""Hello alice from tests""#
+      );
+
+      let response = client
+        .post(format!("http://127.0.0.1:{port}/handlers/answer"))
+        .header("Content-Type", "application/json")
+        .body("[]")
+        .send()
+        .await
+        .unwrap();
+      assert_eq!(response.text().await.unwrap(), "42");
+
+      // only optional parameters can be left out, additional ones are rejected
+      for body in [r#"[]"#, r#"["alice", "Hi", "extra"]"#] {
+        let response = client
+          .post(format!("http://127.0.0.1:{port}/handlers/greet"))
+          .header("Content-Type", "application/json")
+          .body(body)
+          .send()
+          .await
+          .unwrap();
+        assert_eq!(response.status(), 400, "{body}");
+      }
+    });
+  }
+
+  #[test]
+  fn max_body_size() {
+    let mut server = ERPCServer::new(0, vec!["http://localhost".to_string()], false);
+    server.register_handler(|data: String| async move { data.len() }, "length");
+    server.set_max_body_size(128 * 1024);
+
+    serve(server, |_, port| async move {
+      let target = ERPCTarget::new("http://127.0.0.1".to_string(), port, TargetType::HTTPServer);
+
+      let result: usize = target
+        .call("length".to_string(), vec!["a".repeat(100 * 1024)])
+        .await
+        .unwrap();
+      assert_eq!(result, 100 * 1024);
+
+      let result = target
+        .call::<_, usize>("length".to_string(), vec!["a".repeat(200 * 1024)])
+        .await;
+      assert!(matches!(result, Err(ERPCError::PayloadTooLarge(_))));
+    });
+  }
+
+  #[test]
+  fn binary_payloads() {
+    let mut server = ERPCServer::new(0, vec!["http://localhost".to_string()], true);
+    server.register_handler(
+      |data: Binary| async move { Binary(data.0.into_iter().rev().collect()) },
+      "reverse",
+    );
+    server.register_handler(|v: serde_json::Value| async move { v }, "echo");
+
+    serve(server, |_, port| async move {
+      let response = reqwest::Client::new()
+        .post(format!("http://127.0.0.1:{port}/handlers/reverse"))
+        .header("Content-Type", "application/json")
+        .body(r#"[{"$erpc":"binary","data":"AQID"}]"#)
+        .send()
+        .await
+        .unwrap();
+      assert_eq!(
+        response.text().await.unwrap(),
+        r#"{"$erpc":"binary","data":"AwIB"}"#
+      );
+
+      // objects which merely look similar are passed on unchanged
+      for value in [
+        r#"{"$binary":"AQID"}"#,
+        r#"{"$erpc":"binary"}"#,
+        r#"{"$erpc":"binary","data":"AQID","name":"file"}"#,
+      ] {
+        let response = reqwest::Client::new()
+          .post(format!("http://127.0.0.1:{port}/handlers/echo"))
+          .body(format!("[{value}]"))
+          .send()
+          .await
+          .unwrap();
+        assert_eq!(response.text().await.unwrap(), value);
+      }
+
+      let (mut socket, _) =
+        tokio_tungstenite::connect_async(format!("ws://127.0.0.1:{port}/ws/Frontend"))
+          .await
+          .unwrap();
+      let request = serde_json::json!({
+        "id": "1",
+        "request": { "identifier": "reverse", "parameters": [binary::encode(&[1, 2, 3])] }
+      });
+      socket
+        .send(Message::binary(binary::to_frame(&request).unwrap()))
+        .await
+        .unwrap();
+      let response = match socket.next().await.unwrap().unwrap() {
+        Message::Binary(v) => binary::from_frame(&v).unwrap(),
+        v => panic!("Expected binary frame, got {v:?}"),
+      };
+      assert_eq!(
+        binary::decode(&response["body"]["Ok"]["body"]),
+        Some(vec![3, 2, 1])
+      );
+    });
+  }
+
+  #[test]
+  fn msgpack_encoding() {
+    let mut server = ERPCServer::new(0, vec!["http://localhost".to_string()], true);
+    server.register_handler(
+      |a: String, b: Binary| async move { format!("{a}{}", b.0.len()) },
+      "describe",
+    );
+    server.register_handler(
+      |data: Binary| async move { Binary(data.0.into_iter().rev().collect()) },
+      "reverse",
+    );
+
+    serve(server, |_, port| async move {
+      let mut target =
+        ERPCTarget::new("http://127.0.0.1".to_string(), port, TargetType::HTTPServer);
+      target.set_encoding(Encoding::MessagePack);
+      let result: String = target
+        .call(
+          "describe".to_string(),
+          vec![serde_json::json!("length "), binary::encode(&[1, 2, 3])],
+        )
+        .await
+        .unwrap();
+      assert_eq!(result, "length 3");
+
+      let response = reqwest::Client::new()
+        .post(format!("http://127.0.0.1:{port}/handlers/describe"))
+        .header("Content-Type", "application/msgpack")
+        .header("Accept", "application/json")
+        .body(
+          Encoding::MessagePack
+            .serialize(&vec![serde_json::json!("a"), binary::encode(&[1])])
+            .unwrap(),
+        )
+        .send()
+        .await
+        .unwrap();
+      assert_eq!(response.text().await.unwrap(), r#""a1""#);
+
+      // binary values are native bin, i.e. [bin [1, 2, 3]] and bin [3, 2, 1]
+      let response = reqwest::Client::new()
+        .post(format!("http://127.0.0.1:{port}/handlers/reverse"))
+        .header("Content-Type", "application/msgpack")
+        .header("Accept", "application/msgpack")
+        .body(vec![0x91, 0xc4, 3, 1, 2, 3])
+        .send()
+        .await
+        .unwrap();
+      assert_eq!(
+        response.bytes().await.unwrap().as_ref(),
+        &[0xc4, 3, 3, 2, 1]
+      );
+
+      let mut request = format!("ws://127.0.0.1:{port}/ws/Frontend")
+        .into_client_request()
+        .unwrap();
+      request
+        .headers_mut()
+        .insert("sec-websocket-protocol", "msgpack".parse().unwrap());
+      let (mut socket, response) = tokio_tungstenite::connect_async(request).await.unwrap();
+      assert_eq!(
+        response.headers().get("sec-websocket-protocol").unwrap(),
+        "msgpack"
+      );
+
+      let request = serde_json::json!({
+        "id": "1",
+        "request": { "identifier": "describe", "parameters": ["b", binary::encode(&[1, 2])] }
+      });
+      socket
+        .send(Message::binary(
+          Encoding::MessagePack.serialize(&request).unwrap(),
+        ))
+        .await
+        .unwrap();
+      let response: serde_json::Value = match socket.next().await.unwrap().unwrap() {
+        Message::Binary(v) => Encoding::MessagePack.deserialize(&v).unwrap(),
+        v => panic!("Expected binary frame, got {v:?}"),
+      };
+      assert_eq!(response["body"]["Ok"]["body"], "b2");
+
+      let response = reqwest::Client::new()
+        .get(format!("http://127.0.0.1:{port}/ws/Frontend?encoding=xml"))
+        .header("connection", "upgrade")
+        .header("upgrade", "websocket")
+        .header("sec-websocket-version", "13")
+        .header("sec-websocket-key", "dGhlIHNhbXBsZSBub25jZQ==")
+        .send()
+        .await
+        .unwrap();
+      assert_eq!(response.status(), 400);
+    });
+  }
+
+  #[test]
+  fn socket_stream() {
+    let mut server = ERPCServer::new(0, vec!["http://localhost".to_string()], true);
+    server.register_stream_handler(
+      |count: u32| async move { futures_util::stream::iter(0..count) },
+      "count",
+    );
+
+    serve(server, |_, port| async move {
+      let response = reqwest::Client::new()
+        .post(format!("http://127.0.0.1:{port}/handlers/count"))
+        .body("[3]")
+        .send()
+        .await
+        .unwrap();
+      assert_eq!(response.status(), 406);
+
+      let (mut socket, _) =
+        tokio_tungstenite::connect_async(format!("ws://127.0.0.1:{port}/ws/Frontend"))
+          .await
+          .unwrap();
+      socket
+        .send(Message::text(
+          r#"{"id":"1","request":{"identifier":"count","parameters":[3]}}"#,
+        ))
+        .await
+        .unwrap();
+
+      let mut messages = Vec::new();
+      for _ in 0..4 {
+        let message = socket.next().await.unwrap().unwrap();
+        messages
+          .push(serde_json::from_str::<serde_json::Value>(message.to_text().unwrap()).unwrap());
+      }
+      assert_eq!(
+        messages,
+        vec![
+          serde_json::json!({ "type": "StreamItem", "id": "1", "item": 0 }),
+          serde_json::json!({ "type": "StreamItem", "id": "1", "item": 1 }),
+          serde_json::json!({ "type": "StreamItem", "id": "1", "item": 2 }),
+          serde_json::json!({ "type": "StreamEnd", "id": "1", "done": true }),
+        ]
+      );
+    });
+  }
+
+  #[test]
+  fn http_stream() {
+    let mut server = ERPCServer::new(0, vec!["http://localhost".to_string()], false);
+    server.register_stream_handler(
+      |count: u32| async move { futures_util::stream::iter(0..count) },
+      "count",
+    );
+    server.register_handler(|| async move { "single" }, "single");
+
+    serve(server, |_, port| async move {
+      let target = ERPCTarget::new("http://127.0.0.1".to_string(), port, TargetType::HTTPServer);
+      let items: Vec<_> = target
+        .stream("count".to_string(), vec![3])
+        .await
+        .unwrap()
+        .map(|v| v.unwrap())
+        .collect()
+        .await;
+      assert_eq!(items, vec![0, 1, 2]);
+
+      let items: Vec<_> = target
+        .stream::<i32>("single".to_string(), vec![])
+        .await
+        .unwrap()
+        .map(|v| v.unwrap())
+        .collect()
+        .await;
+      assert_eq!(items, vec!["single"]);
+
+      let result = target.stream::<i32>("unknown".to_string(), vec![]).await;
+      assert!(matches!(result, Err(ERPCError::HandlerNotFound(_))));
+
+      let response = reqwest::Client::new()
+        .post(format!("http://127.0.0.1:{port}/handlers/count"))
+        .header("Accept", "text/event-stream")
+        .body("[1]")
+        .send()
+        .await
+        .unwrap();
+      assert_eq!(
+        response.headers().get("content-type").unwrap(),
+        "text/event-stream"
+      );
+      let body = response.text().await.unwrap();
+      let events: Vec<_> = body
+        .lines()
+        .filter_map(|v| v.strip_prefix("event:"))
+        .collect();
+      assert_eq!(events, vec!["StreamItem", "StreamEnd"]);
+    });
+  }
+
+  #[test]
+  fn notifications() {
+    let mut server = ERPCServer::new(0, vec!["http://localhost".to_string()], true);
+    let (sender, reciever) = flume::unbounded::<String>();
+    server.register_handler(
+      move |event: String| {
+        let sender = sender.clone();
+        async move {
+          sender.send(event).unwrap();
+        }
+      },
+      "track",
+    );
+
+    serve(server, |_, port| async move {
+      let target = ERPCTarget::new("http://127.0.0.1".to_string(), port, TargetType::HTTPServer);
+      target
+        .notify("track".to_string(), vec!["http"])
+        .await
+        .unwrap();
+      assert_eq!(reciever.recv_async().await.unwrap(), "http");
+
+      let result = target.notify("unknown".to_string(), vec!["http"]).await;
+      assert!(matches!(result, Err(ERPCError::HandlerNotFound(_))));
+
+      let (mut socket, _) =
+        tokio_tungstenite::connect_async(format!("ws://127.0.0.1:{port}/ws/Frontend"))
+          .await
+          .unwrap();
+      socket
+        .send(Message::text(
+          r#"{"request":{"identifier":"track","parameters":["socket"]}}"#,
+        ))
+        .await
+        .unwrap();
+      assert_eq!(reciever.recv_async().await.unwrap(), "socket");
+
+      // no response is sent for notifications
+      let next = tokio::time::timeout(Duration::from_millis(200), socket.next()).await;
+      assert!(next.is_err());
+    });
+  }
+
+  #[test]
+  fn broadcast() {
+    let server = ERPCServer::new(0, vec!["http://localhost".to_string()], true);
+
+    serve(server, |server, port| async move {
+      let connect = |role: &'static str, name: &'static str| async move {
+        let (socket, _) =
+          tokio_tungstenite::connect_async(format!("ws://127.0.0.1:{port}/ws/{role}"))
+            .await
+            .unwrap();
+        let (mut sender, mut reciever) = socket.split();
+        tokio::spawn(async move {
+          while let Some(Ok(Message::Text(message))) = reciever.next().await {
+            let request: serde_json::Value = serde_json::from_str(&message).unwrap();
+            let response = serde_json::json!({
+              "id": request["id"],
+              "body": { "Ok": { "body": format!("{name}: {}", request["request"]["parameters"][0]) } }
+            });
+            sender
+              .send(Message::text(response.to_string()))
+              .await
+              .unwrap();
+          }
+          sender.close().await.ok();
+        });
+      };
+      connect("Frontend", "a").await;
+      connect("Frontend", "b").await;
+      connect("Admin", "c").await;
+      sleep(Duration::from_millis(200)).await;
+
+      let mut results: Vec<String> = server
+        .broadcast("Frontend", "greet".to_string(), vec!["hi"], None)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|(_, v)| serde_json::from_value(v.unwrap()).unwrap())
+        .collect();
+      results.sort();
+      assert_eq!(results, vec![r#"a: "hi""#, r#"b: "hi""#]);
+
+      let sockets = server.sockets("Frontend");
+      assert_eq!(sockets.len(), 2);
+      sockets[0].close(1000, String::new()).unwrap();
+      sleep(Duration::from_millis(200)).await;
+      assert_eq!(server.sockets("Frontend").len(), 1);
+      assert_eq!(server.sockets("Admin").len(), 1);
+
+      let results = server
+        .broadcast("Nobody", "greet".to_string(), vec!["hi"], None)
+        .await
+        .unwrap();
+      assert!(results.is_empty());
+    });
+  }
+
+  #[test]
+  fn topics() {
+    let pub_sub = Arc::new(LocalPubSub::default());
+    let mut first = ERPCServer::new(0, vec!["http://localhost".to_string()], true);
+    let mut second = ERPCServer::new(0, vec!["http://localhost".to_string()], true);
+    first.set_pub_sub(pub_sub.clone());
+    second.set_pub_sub(pub_sub);
+    first.set_guard(Box::new(|metadata| {
+      Box::pin(async move {
+        match metadata.topic.as_deref() {
+          Some("secret") => Err(ERPCError::Forbidden("Secret topic".to_string())),
+          _ => Ok(None),
+        }
+      })
+    }));
+
+    tokio::runtime::Runtime::new()
+      .unwrap()
+      .block_on(async move {
+        let first_port = start(&first);
+        let second_port = start(&second);
+
+        // answers requests and passes on notifications
+        let connect = |port: u16, name: &'static str| async move {
+          let (socket, _) =
+            tokio_tungstenite::connect_async(format!("ws://127.0.0.1:{port}/ws/Frontend"))
+              .await
+              .unwrap();
+          let (mut sender, mut reciever) = socket.split();
+          let (outgoing, outgoing_reciever) = flume::unbounded::<String>();
+          let (notifications, notification_reciever) = flume::unbounded::<serde_json::Value>();
+          tokio::spawn(async move {
+            while let Ok(message) = outgoing_reciever.recv_async().await {
+              sender.send(Message::text(message)).await.unwrap();
+            }
+          });
+          let responses = outgoing.clone();
+          tokio::spawn(async move {
+            while let Some(Ok(Message::Text(message))) = reciever.next().await {
+              let message: serde_json::Value = serde_json::from_str(&message).unwrap();
+              if message.get("id").is_none() {
+                notifications.send(message).unwrap();
+                continue;
+              }
+              let response = serde_json::json!({
+                "id": message["id"],
+                "body": { "Ok": { "body": name } }
+              });
+              responses.send(response.to_string()).unwrap();
+            }
+          });
+          (outgoing, notification_reciever)
+        };
+
+        let (a, a_notifications) = connect(first_port, "a").await;
+        let (_b, b_notifications) = connect(second_port, "b").await;
+        a.send(r#"{"subscribe":"chat"}"#.to_string()).unwrap();
+        a.send(r#"{"subscribe":"secret"}"#.to_string()).unwrap();
+        sleep(Duration::from_millis(200)).await;
+        let b_id = second.sockets("Frontend")[0].id.clone();
+        second.subscribe(&b_id, "chat").unwrap();
+
+        assert_eq!(
+          first
+            .publish("chat", "message".to_string(), vec!["hi"])
+            .unwrap(),
+          2
+        );
+        for notifications in [&a_notifications, &b_notifications] {
+          let notification = notifications.recv_async().await.unwrap();
+          assert_eq!(notification["request"]["identifier"], "message");
+          assert_eq!(notification["request"]["parameters"][0], "hi");
+        }
+        assert_eq!(
+          first
+            .publish("secret", "message".to_string(), vec!["hi"])
+            .unwrap(),
+          0
+        );
+
+        let mut results: Vec<String> = second
+          .publish_call("chat", "name".to_string(), Vec::<()>::new(), None)
+          .await
+          .unwrap()
+          .into_iter()
+          .map(|(_, v)| serde_json::from_value(v.unwrap()).unwrap())
+          .collect();
+        results.sort();
+        assert_eq!(results, vec!["a", "b"]);
+
+        a.send(r#"{"unsubscribe":"chat"}"#.to_string()).unwrap();
+        sleep(Duration::from_millis(100)).await;
+        second.unsubscribe(&b_id, "chat");
+        assert_eq!(
+          first
+            .publish("chat", "message".to_string(), vec!["hi"])
+            .unwrap(),
+          0
+        );
+
+        first.stop().unwrap();
+        second.stop().unwrap();
+      });
+  }
+
+  #[test]
+  fn http_envelope() {
+    let mut server = ERPCServer::new(0, vec!["http://localhost".to_string()], false);
+    server.register_handler(
+      |context: RequestContext, a: i32, b: i32| async move {
+        format!("{} {}", context.request_id, a + b)
+      },
+      "add",
+    );
+
+    serve(server, |_, port| async move {
+      let client = reqwest::Client::new();
+      let post = |body: &'static str| {
+        client
+          .post(format!("http://127.0.0.1:{port}/handlers/add"))
+          .header("Content-Type", "application/json")
+          .body(body)
+          .send()
+      };
+
+      let response = post(r#"{"version":1,"id":"abc","identifier":"add","parameters":[1,2]}"#)
+        .await
+        .unwrap();
+      assert_eq!(response.text().await.unwrap(), r#""abc 3""#);
+
+      // unversioned bodies carry the bare parameters
+      let response = post("[1,2]").await.unwrap();
+      assert!(response.text().await.unwrap().ends_with(" 3\""));
+
+      // fields added by newer clients are ignored
+      let response =
+        post(r#"{"version":1,"id":"abc","identifier":"add","parameters":[1,2],"extra":true}"#)
+          .await
+          .unwrap();
+      assert_eq!(response.text().await.unwrap(), r#""abc 3""#);
+
+      // envelopes of newer clients are read as the negotiated version
+      let response = post(r#"{"version":99,"identifier":"add","parameters":[1,2]}"#)
+        .await
+        .unwrap();
+      assert_eq!(response.status(), 200);
+
+      let response = post(r#"{"version":0,"identifier":"add","parameters":[1,2]}"#)
+        .await
+        .unwrap();
+      assert_eq!(response.status(), 426);
+
+      for body in [
+        r#"{"version":1,"identifier":"subtract","parameters":[1,2]}"#,
+        r#"{"version":1,"id":"","identifier":"add","parameters":[1,2]}"#,
+        r#"{"identifier":"add","parameters":[1,2]}"#,
+        r#""add""#,
+      ] {
+        let response = post(body).await.unwrap();
+        assert_eq!(response.status(), 400, "{body}");
+      }
+    });
+  }
+
+  #[test]
+  fn handler_listing() {
+    let mut server = ERPCServer::new(0, vec!["http://localhost".to_string()], false);
+    server.register_handler(|a: i32, b: i32| async move { a + b }, "add");
+    server.register_handler(|| async move { "pong" }, "ping");
+    server
+      .describe_handler(
+        "add",
+        HandlerMetadata {
+          description: Some("Adds two numbers".to_string()),
+          parameters: Some(serde_json::json!([{ "type": "integer" }, { "type": "integer" }])),
+        },
+      )
+      .unwrap();
+    assert!(matches!(
+      server.describe_handler("subtract", HandlerMetadata::default()),
+      Err(ERPCError::HandlerNotFound(_))
+    ));
+    server.set_guard(Box::new(|metadata| {
+      Box::pin(async move {
+        match metadata.headers.get("authorization").map(|v| v.as_str()) {
+          Some("Bearer secret") => Ok(None),
+          _ => Err(ERPCError::Unauthorized("Missing token".to_string())),
+        }
+      })
+    }));
+    server.set_introspection(true);
+
+    let handlers = server.handlers();
+    assert_eq!(
+      handlers
+        .iter()
+        .map(|v| v.identifier.as_str())
+        .collect::<Vec<_>>(),
+      vec!["add", "ping"]
+    );
+    assert_eq!(handlers[1].metadata, HandlerMetadata::default());
+
+    // the listing is opt-in
+    let mut disabled = ERPCServer::new(0, vec!["http://localhost".to_string()], false);
+    disabled.register_handler(|| async move { "pong" }, "ping");
+
+    tokio::runtime::Runtime::new()
+      .unwrap()
+      .block_on(async move {
+        let port = start(&server);
+        let disabled_port = start(&disabled);
+        let client = reqwest::Client::new();
+
+        let response = client
+          .get(format!("http://127.0.0.1:{port}/handlers"))
+          .send()
+          .await
+          .unwrap();
+        assert_eq!(response.status(), 401);
+
+        let response = client
+          .get(format!("http://127.0.0.1:{port}/handlers"))
+          .header("authorization", "Bearer secret")
+          .send()
+          .await
+          .unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(
+          serde_json::from_str::<serde_json::Value>(&response.text().await.unwrap()).unwrap(),
+          serde_json::json!([
+            {
+              "identifier": "add",
+              "description": "Adds two numbers",
+              "parameters": [{ "type": "integer" }, { "type": "integer" }]
+            },
+            { "identifier": "ping" }
+          ])
+        );
+
+        // calls are still routed to the handlers
+        let response = client
+          .post(format!("http://127.0.0.1:{port}/handlers/add"))
+          .header("authorization", "Bearer secret")
+          .body("[1,2]")
+          .send()
+          .await
+          .unwrap();
+        assert_eq!(response.text().await.unwrap(), "3");
+
+        let response = client
+          .get(format!("http://127.0.0.1:{disabled_port}/handlers"))
+          .send()
+          .await
+          .unwrap();
+        assert_eq!(response.status(), 404);
+
+        server.stop().unwrap();
+        disabled.stop().unwrap();
+      });
+  }
+
+  #[test]
+  fn version_negotiation() {
+    let mut server = ERPCServer::new(0, vec!["http://localhost".to_string()], true);
+    server.register_handler(
+      |context: RequestContext| async move { context.metadata.protocol_version },
+      "version",
+    );
+
+    serve(server, |server, port| async move {
+      let client = reqwest::Client::new();
+      let post = |version: Option<&'static str>| {
+        let mut request = client
+          .post(format!("http://127.0.0.1:{port}/handlers/version"))
+          .header("Content-Type", "application/json")
+          .body("[]");
+        if let Some(version) = version {
+          request = request.header(VERSION_HEADER, version);
+        }
+        request.send()
+      };
+
+      // peers without version are treated as speaking the oldest supported one
+      for (version, negotiated) in [
+        (None, MIN_PROTOCOL_VERSION),
+        (Some("1"), 1),
+        (Some("99"), PROTOCOL_VERSION),
+      ] {
+        let response = post(version).await.unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.headers()[VERSION_HEADER], negotiated.to_string());
+        assert_eq!(response.text().await.unwrap(), negotiated.to_string());
+      }
+      for version in ["0", "latest"] {
+        let response = post(Some(version)).await.unwrap();
+        assert_eq!(response.status(), 426);
+      }
+
+      // a newer peer sends its own version in both the header and the envelope
+      let response = client
+        .post(format!("http://127.0.0.1:{port}/handlers/version"))
+        .header("Content-Type", "application/json")
+        .header(VERSION_HEADER, "3")
+        .body(r#"{"version":3,"identifier":"version","parameters":[]}"#)
+        .send()
+        .await
+        .unwrap();
+      assert_eq!(response.status(), 200);
+      assert_eq!(
+        response.headers()[VERSION_HEADER],
+        PROTOCOL_VERSION.to_string()
+      );
+      assert_eq!(response.text().await.unwrap(), PROTOCOL_VERSION.to_string());
+
+      match tokio_tungstenite::connect_async(format!("ws://127.0.0.1:{port}/ws/Frontend?version=0"))
+        .await
+      {
+        Err(tokio_tungstenite::tungstenite::Error::Http(response)) => {
+          assert_eq!(response.status(), 426)
+        }
+        _ => panic!("Expected the handshake to be rejected"),
+      }
+      let (_socket, response) =
+        tokio_tungstenite::connect_async(format!("ws://127.0.0.1:{port}/ws/Frontend?version=99"))
+          .await
+          .unwrap();
+      assert_eq!(
+        response.headers()[VERSION_HEADER],
+        PROTOCOL_VERSION.to_string()
+      );
+      sleep(Duration::from_millis(100)).await;
+      assert_eq!(server.sockets("Frontend")[0].version, PROTOCOL_VERSION);
+
+      let mut target = ERPCTarget::new(
+        "http://127.0.0.1".to_string(),
+        port,
+        TargetType::SocketServer,
+      );
+      target.set_role("Backend".to_string());
+      assert_eq!(target.protocol_version(), None);
+      target.connect().await.unwrap();
+      assert_eq!(target.protocol_version(), Some(PROTOCOL_VERSION));
+      target.close().unwrap();
+    });
+  }
+
+  #[test]
+  fn tagged_socket_messages() {
+    let mut server = ERPCServer::new(0, vec!["http://localhost".to_string()], true);
+    server.register_handler(|a: i32, b: i32| async move { a + b }, "add");
+
+    serve(server, |_, port| async move {
+      let (mut socket, _) = tokio_tungstenite::connect_async(format!(
+        "ws://127.0.0.1:{port}/ws/Frontend?version={TAGGED_VERSION}"
+      ))
+      .await
+      .unwrap();
+      for message in [
+        r#"{"type":"Request","id":"1","request":{"identifier":"add","parameters":[1,2]}}"#,
+        r#"{"type":"Request","id":"2","oops":true}"#,
+        // untagged messages are only understood from legacy peers
+        r#"{"id":"3","request":{"identifier":"add","parameters":[1,2]}}"#,
+        "not json",
+        r#"{"type":"Ping","id":"4"}"#,
+      ] {
+        socket.send(Message::text(message)).await.unwrap();
+      }
+
+      let mut messages = HashMap::new();
+      for _ in 0..5 {
+        let message: serde_json::Value = match socket.next().await {
+          Some(Ok(Message::Text(v))) => serde_json::from_str(&v).unwrap(),
+          v => panic!("Expected a text frame, got {v:?}"),
+        };
+        let key = message["id"].as_str().unwrap_or("none").to_string();
+        messages.insert(key, message);
+      }
+
+      assert_eq!(messages["1"]["type"], "Response");
+      assert_eq!(messages["1"]["body"]["Ok"]["body"], 3);
+      for id in ["2", "3", "none"] {
+        assert_eq!(messages[id]["type"], "Error");
+        assert!(messages[id]["message"].is_string());
+      }
+      assert_eq!(messages["4"]["type"], "Pong");
+
+      // the connection survived the malformed frames
+      socket
+        .send(Message::text(
+          r#"{"type":"Request","id":"5","request":{"identifier":"add","parameters":[2,2]}}"#,
+        ))
+        .await
+        .unwrap();
+      match socket.next().await {
+        Some(Ok(Message::Text(v))) => {
+          let message: serde_json::Value = serde_json::from_str(&v).unwrap();
+          assert_eq!(message["body"]["Ok"]["body"], 4);
+        }
+        v => panic!("Expected a text frame, got {v:?}"),
+      }
     });
-    server.run().unwrap().await;
   }
 }
@@ -0,0 +1,341 @@
+#[cfg(test)]
+mod tests {
+  use std::time::Duration;
+
+  use futures_util::StreamExt;
+  use tokio::time::sleep;
+
+  use crate::erpc::{
+    client::{InFlightPolicy, ReconnectPolicy},
+    context::RequestContext,
+    error::ERPCError,
+    protocol::{
+      socket::{SocketMessage, StreamEnd, StreamError, StreamItem},
+      ApplicationError, BatchResult, Request,
+    },
+    server::ERPCServer,
+    target::{ERPCTarget, TargetType},
+    tests::util::{connect_socket, free_port, serve},
+  };
+
+  #[test]
+  fn http_call() {
+    let mut server = ERPCServer::new(0, vec!["http://localhost".to_string()], false);
+    server.register_handler(|a: i32, b: i32| async move { a + b }, "add");
+    server.register_raw_handler(
+      Box::new(|_, _| {
+        Box::pin(async {
+          Err(ERPCError::Application(ApplicationError {
+            code: "wrongPassword".to_string(),
+            message: "The password is wrong".to_string(),
+            data: Some(serde_json::json!({ "attempts": 3 })),
+          }))
+        })
+      }),
+      "login",
+    );
+
+    serve(server, |_, port| async move {
+      let target = ERPCTarget::new("http://127.0.0.1".to_string(), port, TargetType::HTTPServer);
+
+      let result: i32 = target.call("add".to_string(), vec![1, 2]).await.unwrap();
+      assert_eq!(result, 3);
+
+      let result = target.call::<i32, i32>("unknown".to_string(), vec![]).await;
+      assert!(matches!(result, Err(ERPCError::HandlerNotFound(v)) if v == "unknown"));
+
+      let result = target.call::<i32, i32>("login".to_string(), vec![]).await;
+      match result {
+        Err(ERPCError::Application(err)) => {
+          assert_eq!(err.code, "wrongPassword");
+          assert_eq!(err.message, "The password is wrong");
+          assert_eq!(err.data, Some(serde_json::json!({ "attempts": 3 })));
+        }
+        v => panic!("Expected application error, got {v:?}"),
+      }
+    });
+  }
+
+  #[test]
+  fn timeout() {
+    let mut server = ERPCServer::new(0, vec!["http://localhost".to_string()], false);
+    server.register_handler(
+      |ms: u64| async move {
+        sleep(Duration::from_millis(ms)).await;
+      },
+      "slow",
+    );
+
+    serve(server, |_, port| async move {
+      let mut target =
+        ERPCTarget::new("http://127.0.0.1".to_string(), port, TargetType::HTTPServer);
+      target.set_timeout(Some(Duration::from_millis(200)));
+      let result = target.call::<u64, ()>("slow".to_string(), vec![2000]).await;
+      assert!(matches!(result, Err(ERPCError::Timeout)));
+
+      let (mut target, _incoming, _outgoing, _) = connect_socket().await;
+      target.set_timeout(Some(Duration::from_millis(200)));
+      let result = target.call::<u64, ()>("slow".to_string(), vec![2000]).await;
+      assert!(matches!(result, Err(ERPCError::Timeout)));
+      assert_eq!(target.open_requests(), 0);
+
+      // cancelling drops the call
+      let call = target.call::<u64, ()>("slow".to_string(), vec![2000]);
+      assert!(tokio::time::timeout(Duration::from_millis(50), call)
+        .await
+        .is_err());
+      assert_eq!(target.open_requests(), 0);
+    });
+  }
+
+  #[tokio::test]
+  async fn socket_disconnect() {
+    let (target, incoming, outgoing, listening) = connect_socket().await;
+
+    let t2 = target.clone();
+    let call = tokio::spawn(async move { t2.call::<(), ()>("some".to_string(), vec![]).await });
+    outgoing.recv_async().await.unwrap();
+
+    drop(incoming);
+    listening.await.unwrap();
+
+    assert!(matches!(call.await.unwrap(), Err(ERPCError::SocketClosed)));
+    assert!(!target.is_connected());
+  }
+
+  #[tokio::test]
+  async fn socket_send_failure() {
+    let (target, _incoming, outgoing, _) = connect_socket().await;
+
+    drop(outgoing);
+    let result = target.call::<(), ()>("some".to_string(), vec![]).await;
+    assert!(matches!(result, Err(ERPCError::SocketClosed)));
+  }
+
+  #[tokio::test]
+  async fn socket_stream() {
+    let (target, incoming, outgoing, _) = connect_socket().await;
+
+    let request_id = |message: SocketMessage| match message {
+      SocketMessage::Request(v) => v.id,
+      _ => panic!("Expected a request"),
+    };
+
+    let stream = target.stream("count".to_string(), vec![2]).await.unwrap();
+    let id = request_id(outgoing.recv_async().await.unwrap());
+    for item in [0, 1] {
+      incoming
+        .send(SocketMessage::StreamItem(StreamItem {
+          id: id.clone(),
+          item: serde_json::json!(item),
+        }))
+        .unwrap();
+    }
+    incoming
+      .send(SocketMessage::StreamEnd(StreamEnd { id, done: true }))
+      .unwrap();
+    let items: Vec<_> = stream.map(|v| v.unwrap()).collect().await;
+    assert_eq!(items, vec![serde_json::json!(0), serde_json::json!(1)]);
+
+    let mut stream = target
+      .stream::<i32>("fail".to_string(), vec![])
+      .await
+      .unwrap();
+    let id = request_id(outgoing.recv_async().await.unwrap());
+    incoming
+      .send(SocketMessage::StreamError(StreamError {
+        id,
+        error: Err("broken".to_string()),
+      }))
+      .unwrap();
+    assert!(matches!(stream.next().await, Some(Err(ERPCError::HandlerFailed(v))) if v == "broken"));
+    assert!(stream.next().await.is_none());
+
+    let mut stream = target
+      .stream::<i32>("count".to_string(), vec![])
+      .await
+      .unwrap();
+    outgoing.recv_async().await.unwrap();
+    drop(incoming);
+    assert!(matches!(
+      stream.next().await,
+      Some(Err(ERPCError::SocketClosed))
+    ));
+  }
+
+  #[test]
+  fn socket_server_reconnect() {
+    let mut server = ERPCServer::new(0, vec!["http://localhost".to_string()], true);
+    server.register_handler(
+      |ms: u64| async move {
+        sleep(Duration::from_millis(ms)).await;
+        ms
+      },
+      "slow",
+    );
+
+    serve(server, |server, port| async move {
+      let target = |in_flight: InFlightPolicy| {
+        let mut target = ERPCTarget::new(
+          "http://127.0.0.1".to_string(),
+          port,
+          TargetType::SocketServer,
+        );
+        target.set_role("Backend".to_string());
+        target.set_reconnect_policy(ReconnectPolicy {
+          initial_delay: Duration::from_millis(20),
+          in_flight,
+          ..Default::default()
+        });
+        target
+      };
+      // closes the connections of all targets from the server side
+      let drop_connections = |server: ERPCServer| async move {
+        sleep(Duration::from_millis(100)).await;
+        for socket in server.sockets("Backend") {
+          socket.close(1001, "Going away".to_string()).unwrap();
+        }
+      };
+
+      let resending = target(InFlightPolicy::Resend);
+      // calls are only kept for resending once the target has connected
+      let result = resending.call::<_, u64>("slow".to_string(), vec![0]).await;
+      assert!(matches!(result, Err(ERPCError::SocketClosed)));
+      resending.connect().await.unwrap();
+      assert!(resending.is_connected());
+      let result: u64 = resending.call("slow".to_string(), vec![0]).await.unwrap();
+      assert_eq!(result, 0);
+
+      let (result, _) = tokio::join!(
+        resending.call::<_, u64>("slow".to_string(), vec![300]),
+        drop_connections(server.clone())
+      );
+      assert_eq!(result.unwrap(), 300);
+      assert!(resending.is_connected());
+
+      let failing = target(InFlightPolicy::Fail);
+      failing.connect().await.unwrap();
+      let (result, _) = tokio::join!(
+        failing.call::<_, u64>("slow".to_string(), vec![300]),
+        drop_connections(server.clone())
+      );
+      assert!(matches!(result, Err(ERPCError::SocketClosed)));
+      sleep(Duration::from_millis(200)).await;
+      assert!(failing.is_connected());
+
+      failing.close().unwrap();
+      sleep(Duration::from_millis(100)).await;
+      assert!(!failing.is_connected());
+      assert_eq!(server.sockets("Backend").len(), 1);
+
+      let mut unreachable = ERPCTarget::new(
+        "http://127.0.0.1".to_string(),
+        free_port(),
+        TargetType::SocketServer,
+      );
+      unreachable.set_reconnect_policy(ReconnectPolicy {
+        initial_delay: Duration::from_millis(10),
+        max_attempts: Some(3),
+        ..Default::default()
+      });
+      assert!(matches!(
+        unreachable.connect().await,
+        Err(ERPCError::Transport(_))
+      ));
+
+      let tls = ERPCTarget::new(
+        "https://127.0.0.1".to_string(),
+        port,
+        TargetType::SocketServer,
+      );
+      assert!(matches!(
+        tls.connect().await,
+        Err(ERPCError::Transport(v)) if v.contains("TLS is not supported")
+      ));
+
+      resending.close().unwrap();
+    });
+  }
+
+  #[test]
+  fn http_batch() {
+    let mut server = ERPCServer::new(0, vec!["http://localhost".to_string()], false);
+    server.register_handler(|a: i32, b: i32| async move { a + b }, "add");
+    server.register_handler(|| async move {}, "secret");
+    server.register_handler(
+      |context: RequestContext| async move { context.request_id },
+      "request_id",
+    );
+    server.register_raw_handler(
+      Box::new(|_, _| {
+        Box::pin(async {
+          Err(ERPCError::Application(ApplicationError {
+            code: "wrongPassword".to_string(),
+            message: "The password is wrong".to_string(),
+            data: None,
+          }))
+        })
+      }),
+      "login",
+    );
+    server.set_guard(Box::new(|metadata| {
+      Box::pin(async move {
+        match metadata.identifier.as_deref() {
+          Some("secret") => Err(ERPCError::Forbidden("Not for you".to_string())),
+          _ => Ok(None),
+        }
+      })
+    }));
+
+    serve(server, |_, port| async move {
+      let target = ERPCTarget::new("http://127.0.0.1".to_string(), port, TargetType::HTTPServer);
+      let request = |identifier: &str, parameters: Vec<serde_json::Value>| Request {
+        identifier: identifier.to_string(),
+        parameters,
+      };
+      let results = target
+        .call_batch(vec![
+          request("add", vec![1.into(), 2.into()]),
+          request("unknown", vec![]),
+          request("login", vec![]),
+          request("secret", vec![]),
+          request("add", vec![3.into(), 4.into()]),
+        ])
+        .await
+        .unwrap();
+
+      assert_eq!(results.len(), 5);
+      assert_eq!(results[0].as_ref().unwrap(), &serde_json::json!(3));
+      assert!(matches!(&results[1], Err(ERPCError::HandlerNotFound(v)) if v == "unknown"));
+      assert!(matches!(&results[2], Err(ERPCError::Application(v)) if v.code == "wrongPassword"));
+      assert!(matches!(&results[3], Err(ERPCError::Forbidden(v)) if v == "Not for you"));
+      assert_eq!(results[4].as_ref().unwrap(), &serde_json::json!(7));
+
+      assert!(target.call_batch(vec![]).await.unwrap().is_empty());
+
+      // every item is an envelope, invalid items fail on their own
+      let response = reqwest::Client::new()
+        .post(format!("http://127.0.0.1:{port}/batch"))
+        .body(
+          r#"[
+            {"version":1,"id":"abc","identifier":"request_id","parameters":[],"extra":true},
+            {"identifier":"add","parameters":[1,2]},
+            {"version":1,"identifier":"add","parameters":[1,2]},
+            {"version":0,"identifier":"add","parameters":[1,2]},
+            {"version":99,"identifier":"add","parameters":[1,2]}
+          ]"#,
+        )
+        .send()
+        .await
+        .unwrap();
+      let results: Vec<BatchResult> =
+        serde_json::from_str(&response.text().await.unwrap()).unwrap();
+      assert_eq!(
+        results.iter().map(|v| v.status).collect::<Vec<_>>(),
+        vec![200, 400, 200, 426, 200]
+      );
+      assert_eq!(results[0].body, "abc");
+      assert_eq!(results[2].body, 3);
+    });
+  }
+}
@@ -0,0 +1,83 @@
+use std::{future::Future, net::TcpListener, time::Duration};
+
+use flume::{Receiver, Sender};
+use tokio::{task::JoinHandle, time::sleep};
+
+use crate::erpc::{
+  protocol::{socket::SocketMessage, PROTOCOL_VERSION},
+  server::ERPCServer,
+  target::{ERPCTarget, TargetType},
+  Socket,
+};
+
+/**
+  Runs the server on the addresses it is configured with and returns the port of the first one.
+  Tests create servers with port 0, so each one is bound to a free port and tests can run in parallel.
+*/
+pub fn start(server: &ERPCServer) -> u16 {
+  let (addresses, fut) = server.run().unwrap();
+  tokio::spawn(fut);
+  addresses[0].port()
+}
+
+/**
+  Runs the test on a new runtime with the server started, stopping the server afterwards
+*/
+pub fn serve<T, F>(server: ERPCServer, test: T)
+where
+  T: FnOnce(ERPCServer, u16) -> F,
+  F: Future<Output = ()>,
+{
+  tokio::runtime::Runtime::new()
+    .unwrap()
+    .block_on(async move {
+      let port = start(&server);
+      test(server.clone(), port).await;
+      server.stop().unwrap();
+    });
+}
+
+/**
+  A port nothing listens on, e.g. to bind the same port on several addresses
+*/
+pub fn free_port() -> u16 {
+  TcpListener::bind("127.0.0.1:0")
+    .unwrap()
+    .local_addr()
+    .unwrap()
+    .port()
+}
+
+/**
+  Connects a browser target to a socket whose other end is driven by the test.
+  Returns the target, the sender of incoming and the reciever of outgoing messages and the task listening on the socket.
+*/
+pub async fn connect_socket() -> (
+  ERPCTarget,
+  Sender<SocketMessage>,
+  Receiver<SocketMessage>,
+  JoinHandle<()>,
+) {
+  let (sender, outgoing) = flume::unbounded();
+  let (incoming, reciever) = flume::unbounded();
+  let target = ERPCTarget::new(String::new(), 0, TargetType::Browser);
+  let mut listener = target.clone();
+  let listening = tokio::spawn(async move {
+    listener
+      .listen_on_socket(Socket {
+        id: "socket".to_string(),
+        sender,
+        reciever,
+        role: "Frontend".to_string(),
+        version: PROTOCOL_VERSION,
+        events: flume::unbounded().1,
+        closer: flume::unbounded().0,
+        calls: Default::default(),
+      })
+      .await;
+  });
+  while !target.is_connected() {
+    sleep(Duration::from_millis(10)).await;
+  }
+  (target, incoming, outgoing, listening)
+}
@@ -0,0 +1,85 @@
+use super::error::ERPCError;
+use std::sync::{Arc, RwLock};
+use tokio_rustls::rustls::{
+  server::{ClientHello, ResolvesServerCert},
+  sign::{self, CertifiedKey},
+  Certificate, PrivateKey, ServerConfig,
+};
+
+/**
+  Serves the currently configured certificate to incoming TLS connections.
+  The certificate can be replaced at any time, new connections will pick up the new one.
+*/
+pub struct CertificateResolver {
+  key: RwLock<Arc<CertifiedKey>>,
+}
+
+impl CertificateResolver {
+  /**
+    Creates a resolver from a PEM encoded certificate chain and private key
+  */
+  pub fn new(certificate: &[u8], key: &[u8]) -> Result<Self, ERPCError> {
+    Ok(CertificateResolver {
+      key: RwLock::new(Arc::new(parse_pem(certificate, key)?)),
+    })
+  }
+
+  /**
+    Replaces the served certificate with the given PEM encoded certificate chain and private key
+  */
+  pub fn reload(&self, certificate: &[u8], key: &[u8]) -> Result<(), ERPCError> {
+    let parsed = parse_pem(certificate, key)?;
+    *self
+      .key
+      .write()
+      .map_err(|err| ERPCError::Internal(format!("Could not lock certificate: {err}")))? =
+      Arc::new(parsed);
+    Ok(())
+  }
+
+  /**
+    Creates a rustls server config which serves the certificates of this resolver
+  */
+  pub fn server_config(self: &Arc<Self>) -> ServerConfig {
+    let mut config = ServerConfig::builder()
+      .with_safe_defaults()
+      .with_no_client_auth()
+      .with_cert_resolver(self.clone());
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    config
+  }
+}
+
+impl ResolvesServerCert for CertificateResolver {
+  fn resolve(&self, _client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+    self.key.read().ok().map(|v| v.clone())
+  }
+}
+
+fn parse_pem(certificate: &[u8], key: &[u8]) -> Result<CertifiedKey, ERPCError> {
+  let certificates = rustls_pemfile::certs(&mut &*certificate)
+    .map_err(|err| ERPCError::Tls(format!("Could not read certificate: {err}")))?
+    .into_iter()
+    .map(Certificate)
+    .collect::<Vec<_>>();
+  if certificates.is_empty() {
+    return Err(ERPCError::Tls(
+      "No certificate found in PEM data".to_string(),
+    ));
+  }
+
+  let key = rustls_pemfile::read_all(&mut &*key)
+    .map_err(|err| ERPCError::Tls(format!("Could not read private key: {err}")))?
+    .into_iter()
+    .find_map(|item| match item {
+      rustls_pemfile::Item::PKCS8Key(v)
+      | rustls_pemfile::Item::RSAKey(v)
+      | rustls_pemfile::Item::ECKey(v) => Some(PrivateKey(v)),
+      _ => None,
+    })
+    .ok_or_else(|| ERPCError::Tls("No private key found in PEM data".to_string()))?;
+  let key = sign::any_supported_type(&key)
+    .map_err(|err| ERPCError::Tls(format!("Unsupported private key: {err}")))?;
+
+  Ok(CertifiedKey::new(certificates, key))
+}
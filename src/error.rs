@@ -0,0 +1,87 @@
+use napi::{Env, JsObject, JsUnknown, ValueType};
+
+use crate::{
+  erpc::{error::ERPCError, protocol::ApplicationError},
+  value,
+};
+
+/**
+  Converts an ERPCError into a JS error which carries the kind of the error in its `code` property.
+  Application errors keep the message, code and data they were thrown with.
+*/
+pub fn to_js_error(env: &Env, err: ERPCError) -> napi::Error {
+  let reason = match &err {
+    ERPCError::Application(v) => v.message.clone(),
+    err => err.to_string(),
+  };
+
+  let create = || -> Result<JsObject, napi::Error> {
+    let mut error = env.create_error(napi::Error::from_reason(reason.clone()))?;
+    match &err {
+      ERPCError::Application(v) => {
+        error.set_named_property("code", env.create_string(&v.code)?)?;
+        if let Some(data) = &v.data {
+          error.set_named_property("data", value::to_js_value(env, data)?)?;
+        }
+      }
+      err => error.set_named_property("code", env.create_string(err.code())?)?,
+    };
+    Ok(error)
+  };
+
+  match create() {
+    Ok(v) => napi::Error::from(v.into_unknown()),
+    Err(_) => napi::Error::from_reason(reason),
+  }
+}
+
+/**
+  Reads a value thrown by a JS handler into an ApplicationError.
+  The code is taken from the `code` or `name` property of the thrown value, the data from its `data` property.
+*/
+pub fn application_error_from_js(env: &Env, value: JsUnknown) -> ApplicationError {
+  let mut err = ApplicationError {
+    code: "Error".to_string(),
+    message: "Unknown error".to_string(),
+    data: None,
+  };
+
+  if !matches!(value.get_type(), Ok(ValueType::Object)) {
+    if let Ok(v) = value
+      .coerce_to_string()
+      .and_then(|v| v.into_utf8()?.into_owned())
+    {
+      err.message = v;
+    }
+    return err;
+  }
+
+  let object: JsObject = unsafe { value.cast() };
+  let string_property = |name: &str| -> Option<String> {
+    let v: JsUnknown = object.get_named_property(name).ok()?;
+    match v.get_type().ok()? {
+      ValueType::String => v
+        .coerce_to_string()
+        .ok()?
+        .into_utf8()
+        .ok()?
+        .into_owned()
+        .ok(),
+      _ => None,
+    }
+  };
+
+  if let Some(code) = string_property("code").or_else(|| string_property("name")) {
+    err.code = code;
+  }
+  if let Some(message) = string_property("message") {
+    err.message = message;
+  }
+  if let Ok(data) = object.get_named_property::<JsUnknown>("data") {
+    if !matches!(data.get_type(), Ok(ValueType::Undefined)) {
+      err.data = value::from_js_value(env, data).ok();
+    }
+  }
+
+  err
+}
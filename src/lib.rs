@@ -1,12 +1,17 @@
 #![deny(clippy::all)]
 
 //TODO: remove unwraps
-//TODO: maybe rework error handling? use custom error type to prevent .map_err calls
 
+mod context;
 mod erpc;
-mod threadsafe_function;
+mod error;
+mod pubsub;
 mod server;
+mod socket;
+mod stream;
 mod target;
+mod threadsafe_function;
+mod value;
 
 #[macro_use]
 extern crate napi_derive;
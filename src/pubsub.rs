@@ -0,0 +1,24 @@
+use crate::erpc::pubsub::LocalPubSub;
+
+/**
+  Keeps track of topic subscriptions. Servers sharing an instance can publish to the sockets of each other.
+*/
+#[napi(js_name = "ERPCPubSub")]
+pub struct ERPCPubSub {
+  pub_sub: LocalPubSub,
+}
+
+#[napi]
+impl ERPCPubSub {
+  #[napi(constructor)]
+  #[allow(clippy::new_without_default)]
+  pub fn new() -> Self {
+    ERPCPubSub {
+      pub_sub: LocalPubSub::default(),
+    }
+  }
+
+  pub fn pub_sub(&self) -> &LocalPubSub {
+    &self.pub_sub
+  }
+}
@@ -3,43 +3,328 @@
 //TODO: remove unwraps
 //TODO: refactoring
 
-use std::{any::Any, convert::Infallible};
+use std::{
+  convert::Infallible,
+  net::{SocketAddr, ToSocketAddrs},
+  sync::{Arc, Mutex},
+  time::Duration,
+};
 
 use napi::{
-  bindgen_prelude::{FromNapiValue, Promise},
-  Env, JsFunction, JsUnknown, NapiRaw,
+  bindgen_prelude::{Buffer, ToNapiValue},
+  Env, JsFunction, JsObject, JsUnknown, NapiRaw, NapiValue, Status,
 };
 use tokio::sync::oneshot;
 
-use crate::erpc::Socket;
+use crate::{
+  context::{to_js_context, RequestMetadata},
+  erpc::{error::ERPCError, protocol::ApplicationError, server::HandlerResponse, Socket},
+  error::{application_error_from_js, to_js_error},
+  pubsub::ERPCPubSub,
+  socket::ERPCSocket,
+  stream::{async_iterator, from_async_iterator},
+  value::{from_js_value, to_js_value},
+};
+
+type HandlerResult = Result<HandlerResponse, ApplicationError>;
+
+fn send_response(channel: oneshot::Sender<HandlerResult>, response: HandlerResult) {
+  if channel.send(response).is_err() {
+    eprintln!("Could not send handler response, the request has been dropped");
+  }
+}
+
+/**
+  Reads the value a JS function returned or resolved to. Async iterables, e.g. from async generators, are streamed.
+*/
+fn to_handler_response(env: &Env, value: JsUnknown) -> Result<HandlerResponse, napi::Error> {
+  match async_iterator(env, &value)? {
+    Some(iterator) => Ok(HandlerResponse::Stream(from_async_iterator(env, iterator)?)),
+    None => Ok(HandlerResponse::Value(from_js_value(env, value)?)),
+  }
+}
+
+/**
+  Calls a JS function and sends its result to the channel, once settled if it returns a promise.
+  Thrown errors and rejections are sent as ApplicationError.
+*/
+fn call_js_function(
+  env: &Env,
+  callback: &JsFunction,
+  this: Option<&JsObject>,
+  args: &[JsUnknown],
+  response_channel: oneshot::Sender<HandlerResult>,
+) -> Result<(), napi::Error> {
+  let response = match callback.call(this, args) {
+    Ok(v) => v,
+    Err(err) => {
+      let thrown =
+        unsafe { JsUnknown::from_raw(env.raw(), ToNapiValue::to_napi_value(env.raw(), err)?)? };
+      send_response(
+        response_channel,
+        Err(application_error_from_js(env, thrown)),
+      );
+      return Ok(());
+    }
+  };
+
+  if !response.is_promise()? {
+    let response = to_handler_response(env, response)?;
+    send_response(response_channel, Ok(response));
+    return Ok(());
+  }
+
+  // the promise is settled via then() on the JS thread to be able to read rejection values
+  let response_channel = Arc::new(Mutex::new(Some(response_channel)));
+  let fulfilled_channel = response_channel.clone();
+  let on_fulfilled = env.create_function_from_closure("onFulfilled", move |ctx| {
+    let value = to_handler_response(ctx.env, ctx.get::<JsUnknown>(0)?)?;
+    if let Some(channel) = fulfilled_channel.lock().ok().and_then(|mut v| v.take()) {
+      send_response(channel, Ok(value));
+    }
+    ctx.env.get_undefined()
+  })?;
+  let on_rejected = env.create_function_from_closure("onRejected", move |ctx| {
+    let err = application_error_from_js(ctx.env, ctx.get::<JsUnknown>(0)?);
+    if let Some(channel) = response_channel.lock().ok().and_then(|mut v| v.take()) {
+      send_response(channel, Err(err));
+    }
+    ctx.env.get_undefined()
+  })?;
+
+  let response: JsObject = unsafe { response.cast() };
+  let then: JsFunction = response.get_named_property("then")?;
+  then.call(Some(&response), &[on_fulfilled, on_rejected])?;
+
+  Ok(())
+}
+
+/**
+  The outcome of a call to a single socket, shaped like the results of Promise.allSettled
+*/
+#[napi(object, object_from_js = false)]
+pub struct BroadcastResult {
+  pub socket_id: String,
+  #[napi(ts_type = "'fulfilled' | 'rejected'")]
+  pub status: String,
+  /**
+    The value the call resolved to, if fulfilled
+  */
+  pub value: Option<JsUnknown>,
+  /**
+    The error the call has been rejected with, if rejected
+  */
+  #[napi(ts_type = "Error")]
+  pub reason: Option<JsUnknown>,
+}
+
+/**
+  Converts the results of calls to several sockets into their outcomes
+*/
+fn to_broadcast_results(
+  env: &Env,
+  results: Vec<(String, Result<serde_json::Value, ERPCError>)>,
+) -> Result<Vec<BroadcastResult>, napi::Error> {
+  results
+    .into_iter()
+    .map(|(socket_id, result)| match result {
+      Ok(v) => Ok(BroadcastResult {
+        socket_id,
+        status: "fulfilled".to_string(),
+        value: Some(to_js_value(env, &v)?),
+        reason: None,
+      }),
+      Err(err) => Ok(BroadcastResult {
+        socket_id,
+        status: "rejected".to_string(),
+        value: None,
+        reason: Some(unsafe {
+          JsUnknown::from_raw(
+            env.raw(),
+            ToNapiValue::to_napi_value(env.raw(), to_js_error(env, err))?,
+          )?
+        }),
+      }),
+    })
+    .collect()
+}
+
+/**
+  A PEM encoded certificate chain and private key, either as buffers or as file paths
+*/
+#[napi(object)]
+pub struct TlsOptions {
+  pub cert: Option<Buffer>,
+  pub key: Option<Buffer>,
+  pub cert_path: Option<String>,
+  pub key_path: Option<String>,
+}
+
+impl TlsOptions {
+  fn read(self) -> Result<(Vec<u8>, Vec<u8>), napi::Error> {
+    let read = |buffer: Option<Buffer>, path: Option<String>, name: &str| match (buffer, path) {
+      (Some(v), _) => Ok(v.to_vec()),
+      (None, Some(path)) => std::fs::read(&path).map_err(|err| {
+        napi::Error::new(
+          Status::InvalidArg,
+          format!("Could not read {name} from {path}: {err}"),
+        )
+      }),
+      (None, None) => Err(napi::Error::new(
+        Status::InvalidArg,
+        format!("Missing {name} in TLS options"),
+      )),
+    };
+
+    Ok((
+      read(self.cert, self.cert_path, "cert")?,
+      read(self.key, self.key_path, "key")?,
+    ))
+  }
+}
 
 #[napi(object)]
 pub struct ServerOptions {
   pub port: u16,
   pub allowed_cors_origins: Vec<String>,
+  /**
+    The host to listen on, e.g. 0.0.0.0 or ::. Defaults to 127.0.0.1
+  */
+  pub host: Option<String>,
+  /**
+    Socket addresses to listen on, e.g. 0.0.0.0:9988 or [::]:9988. Takes precedence over host and port
+  */
+  pub addresses: Option<Vec<String>>,
+  /**
+    Serve https and wss with the given certificate
+  */
+  pub tls: Option<TlsOptions>,
+  /**
+    The maximum size of request bodies and websocket messages in bytes. Defaults to 1 MiB
+  */
+  pub max_body_size: Option<u32>,
+  /**
+    List the registered handlers via GET handlers. Defaults to false
+  */
+  pub introspection: Option<bool>,
+}
+
+/**
+  Optional information about a handler, listed along with its identifier
+*/
+#[napi(object)]
+pub struct HandlerMetadata {
+  /**
+    What the handler does, in human readable form
+  */
+  pub description: Option<String>,
+  /**
+    A schema of the parameters the handler expects, e.g. a JSON schema
+  */
+  pub parameters: Option<serde_json::Value>,
+}
+
+impl From<HandlerMetadata> for crate::erpc::protocol::HandlerMetadata {
+  fn from(v: HandlerMetadata) -> Self {
+    crate::erpc::protocol::HandlerMetadata {
+      description: v.description,
+      parameters: v.parameters,
+    }
+  }
+}
+
+/**
+  A registered handler as listed by the server, with its metadata
+*/
+#[napi(object)]
+pub struct HandlerDescription {
+  pub identifier: String,
+  /**
+    What the handler does, in human readable form
+  */
+  pub description: Option<String>,
+  /**
+    A schema of the parameters the handler expects, e.g. a JSON schema
+  */
+  pub parameters: Option<serde_json::Value>,
+}
+
+impl From<crate::erpc::protocol::HandlerDescription> for HandlerDescription {
+  fn from(v: crate::erpc::protocol::HandlerDescription) -> Self {
+    HandlerDescription {
+      identifier: v.identifier,
+      description: v.metadata.description,
+      parameters: v.metadata.parameters,
+    }
+  }
 }
 
 #[napi(js_name = "ERPCServer")]
 pub struct ERPCServer {
   server: crate::erpc::server::ERPCServer,
+  addresses: Vec<String>,
 }
 
 #[napi]
 impl ERPCServer {
   #[napi(constructor)]
   pub fn new(
+    env: Env,
     options: ServerOptions,
     _server_type: String, // exists for consistency reasons but isn't actually needed
     enable_sockets: bool,
     _role: String, // might become handy in the future
-  ) -> Self {
-    ERPCServer {
-      server: crate::erpc::server::ERPCServer::new(
-        options.port,
-        options.allowed_cors_origins,
-        enable_sockets,
+  ) -> Result<Self, napi::Error> {
+    let mut server = crate::erpc::server::ERPCServer::new(
+      options.port,
+      options.allowed_cors_origins,
+      enable_sockets,
+    );
+
+    let addresses = match (options.addresses, options.host) {
+      (Some(addresses), _) => Some(
+        addresses
+          .iter()
+          .map(|v| {
+            v.parse::<SocketAddr>().map_err(|err| {
+              napi::Error::new(Status::InvalidArg, format!("Invalid address {v}: {err}"))
+            })
+          })
+          .collect::<Result<Vec<_>, _>>()?,
+      ),
+      (None, Some(host)) => Some(
+        (host.as_str(), options.port)
+          .to_socket_addrs()
+          .map_err(|err| {
+            napi::Error::new(Status::InvalidArg, format!("Invalid host {host}: {err}"))
+          })?
+          .collect(),
       ),
+      (None, None) => None,
+    };
+    if let Some(addresses) = addresses {
+      server.set_addresses(addresses);
+    }
+
+    if let Some(max_body_size) = options.max_body_size {
+      server.set_max_body_size(max_body_size.into());
     }
+
+    if let Some(introspection) = options.introspection {
+      server.set_introspection(introspection);
+    }
+
+    if let Some(tls) = options.tls {
+      let (cert, key) = tls.read()?;
+      server
+        .set_tls(&cert, &key)
+        .map_err(|err| to_js_error(&env, err))?;
+    }
+
+    Ok(ERPCServer {
+      server,
+      addresses: Vec::new(),
+    })
   }
 
   #[napi(skip_typescript, js_name = "registerERPCHandler")]
@@ -48,6 +333,7 @@ impl ERPCServer {
     env: Env,
     func: JsFunction,
     identifier: String,
+    metadata: Option<HandlerMetadata>,
   ) -> Result<(), napi::Error> {
     let tsf = crate::threadsafe_function::ThreadsafeFunction::create(
       env.raw(),
@@ -55,84 +341,137 @@ impl ERPCServer {
       0,
       |ctx: crate::threadsafe_function::ThreadSafeCallContext<(
         Vec<serde_json::Value>,
-        oneshot::Sender<serde_json::Value>,
+        crate::erpc::context::RequestContext,
+        oneshot::Sender<HandlerResult>,
       )>| {
-
-        let args = ctx
-          .value
-          .0
+        let (parameters, context, response_channel) = ctx.value;
+        let args = parameters
           .iter()
-          .map(|v| ctx.env.to_js_value(v))
+          .map(|v| to_js_value(&ctx.env, v))
           .collect::<Result<Vec<JsUnknown>, napi::Error>>()?;
+        let this = to_js_context(&ctx.env, context)?;
 
-        let response = ctx.callback.call(None, args.as_slice())?;
-        let response_channel = ctx.value.1;
-
-        if !response.is_promise()? {
-          let response: serde_json::Value = ctx.env.from_js_value(response)?;
-          ctx
-            .env
-            .execute_tokio_future(
-              async move {
-                match response_channel.send(serde_json::to_value(&response)?) {
-                  Ok(_) => {}
-                  Err(err) => {
-                    return Err(napi::Error::from_reason(format!(
-                      "Could not send response: {err}"
-                    )))
-                  }
-                };
-                Ok(())
-              },
-              |_, _| Ok(()),
-            )
-            .unwrap();
-        } else {
-          unsafe {
-            let prm: Promise<serde_json::Value> =
-              Promise::from_napi_value(ctx.env.raw(), response.raw())?;
-            ctx.env.execute_tokio_future(
-              async move {
-                let result = prm.await?;
-                match response_channel.send(serde_json::to_value(result)?) {
-                  Ok(_) => {}
-                  Err(err) => {
-                    return Err(napi::Error::from_reason(format!(
-                      "Could not send response: {err}"
-                    )))
-                  }
-                };
-                Ok(())
-              },
-              |_, _| Ok(()),
-            )?;
-          }
-        };
-
-        Ok(())
+        call_js_function(
+          &ctx.env,
+          &ctx.callback,
+          Some(&this),
+          &args,
+          response_channel,
+        )
       },
     )?;
 
     self.server.register_raw_handler(
-      Box::new(move |input| {
-        let (sender, reciever) = oneshot::channel::<serde_json::Value>();
+      Box::new(move |input, context| {
+        let (sender, reciever) = oneshot::channel::<HandlerResult>();
         let r = tsf.call(
-          (input, sender),
+          (input, context, sender),
           crate::threadsafe_function::ThreadsafeFunctionCallMode::Blocking,
         );
 
         Box::pin(async move {
           match r {
             napi::Status::Ok => {}
-            _ => return Err(format!("Threadsafe function status not ok: {r}")),
+            _ => {
+              return Err(ERPCError::HandlerFailed(format!(
+                "Threadsafe function status not ok: {r}"
+              )))
+            }
           };
           reciever
             .await
-            .map_err(|err| format!("Could not receive response: {err}"))
+            .map_err(|err| ERPCError::HandlerFailed(format!("Could not receive response: {err}")))?
+            .map_err(ERPCError::Application)
         })
       }),
       &identifier,
     );
+    if let Some(metadata) = metadata {
+      self.describe_handler(env, identifier, metadata)?;
+    }
+    Ok(())
+  }
+
+  /**
+    Attaches metadata like a description or a parameter schema to a registered handler
+  */
+  #[napi]
+  pub fn describe_handler(
+    &mut self,
+    env: Env,
+    identifier: String,
+    metadata: HandlerMetadata,
+  ) -> Result<(), napi::Error> {
+    self
+      .server
+      .describe_handler(&identifier, metadata.into())
+      .map_err(|err| to_js_error(&env, err))
+  }
+
+  /**
+    The registered handlers with their metadata, ordered by identifier
+  */
+  #[napi]
+  pub fn handlers(&self) -> Vec<HandlerDescription> {
+    self.server.handlers().into_iter().map(Into::into).collect()
+  }
+
+  /**
+    Sets a guard which is called with the metadata of every call and socket connection before any handler runs.
+    The value it returns or resolves to is passed to handlers as auth context.
+    Throwing rejects the call as unauthorized, or as forbidden when the thrown error has the code ERPC_FORBIDDEN.
+  */
+  #[napi(skip_typescript)]
+  pub fn set_guard(&mut self, env: Env, func: JsFunction) -> Result<(), napi::Error> {
+    let tsf = crate::threadsafe_function::ThreadsafeFunction::create(
+      env.raw(),
+      unsafe { func.raw() },
+      0,
+      |ctx: crate::threadsafe_function::ThreadSafeCallContext<(
+        RequestMetadata,
+        oneshot::Sender<HandlerResult>,
+      )>| {
+        let (metadata, response_channel) = ctx.value;
+        let metadata = unsafe {
+          JsUnknown::from_raw(
+            ctx.env.raw(),
+            ToNapiValue::to_napi_value(ctx.env.raw(), metadata)?,
+          )?
+        };
+        call_js_function(&ctx.env, &ctx.callback, None, &[metadata], response_channel)
+      },
+    )?;
+
+    self.server.set_guard(Box::new(move |metadata| {
+      let (sender, reciever) = oneshot::channel::<HandlerResult>();
+      let r = tsf.call(
+        (metadata.into(), sender),
+        crate::threadsafe_function::ThreadsafeFunctionCallMode::Blocking,
+      );
+
+      Box::pin(async move {
+        match r {
+          napi::Status::Ok => {}
+          _ => {
+            return Err(ERPCError::Internal(format!(
+              "Threadsafe function status not ok: {r}"
+            )))
+          }
+        };
+        let result = reciever
+          .await
+          .map_err(|err| ERPCError::Internal(format!("Could not receive guard result: {err}")))?;
+        match result {
+          Ok(HandlerResponse::Value(serde_json::Value::Null)) => Ok(None),
+          Ok(HandlerResponse::Value(v)) => Ok(Some(v)),
+          Ok(HandlerResponse::Stream(_)) => Err(ERPCError::Internal(
+            "The guard returned a stream instead of an auth context".to_string(),
+          )),
+          Err(err) if err.code == "ERPC_FORBIDDEN" => Err(ERPCError::Forbidden(err.message)),
+          Err(err) => Err(ERPCError::Unauthorized(err.message)),
+        }
+      })
+    }));
     Ok(())
   }
 
@@ -144,48 +483,49 @@ impl ERPCServer {
       0,
       |ctx: crate::threadsafe_function::ThreadSafeCallContext<Socket>| {
         let role = ctx.env.create_string_from_std(ctx.value.role.clone())?;
-        let mut socket = ctx.env.create_object()?;
-        ctx.env.wrap(&mut socket, ctx.value)?;
+        let socket = ERPCSocket::new(ctx.value).into_instance(ctx.env)?;
 
-        ctx
-          .callback
-          .call(None, &[role.into_unknown(), socket.into_unknown()])?;
+        ctx.callback.call(
+          None,
+          &[
+            role.into_unknown(),
+            socket.as_object(ctx.env).into_unknown(),
+          ],
+        )?;
         Ok(())
       },
     )?;
 
     let socket_notifier_channel = self.server.get_socket_notifier().clone();
-    env
-      .execute_tokio_future(
-        async move {
-          loop {
-            let socket = match socket_notifier_channel.recv_async().await {
-              Ok(v) => v,
-              Err(err) => {
-                return Err(napi::Error::from_reason(format!(
-                  "Error while recieving from socket notifier channel: {err}"
-                )))
-              }
-            };
-
-            let r = tsf.call(
-              (socket).to_owned(),
-              crate::threadsafe_function::ThreadsafeFunctionCallMode::Blocking,
-            );
-
-            match r {
-              napi::Status::Ok => {}
-              _ => {
-                return Err(napi::Error::from_reason(format!(
-                  "Threadsafe function status not ok: {r}"
-                )))
-              }
+    env.execute_tokio_future(
+      async move {
+        loop {
+          let socket = match socket_notifier_channel.recv_async().await {
+            Ok(v) => v,
+            Err(err) => {
+              return Err(napi::Error::from_reason(format!(
+                "Error while recieving from socket notifier channel: {err}"
+              )))
+            }
+          };
+
+          let r = tsf.call(
+            (socket).to_owned(),
+            crate::threadsafe_function::ThreadsafeFunctionCallMode::Blocking,
+          );
+
+          match r {
+            napi::Status::Ok => {}
+            _ => {
+              return Err(napi::Error::from_reason(format!(
+                "Threadsafe function status not ok: {r}"
+              )))
             }
           }
-        },
-        |_, _: Infallible| Ok(()),
-      )
-      .unwrap();
+        }
+      },
+      |_, _: Infallible| Ok(()),
+    )?;
 
     Ok(())
   }
@@ -193,31 +533,170 @@ impl ERPCServer {
   /**
     Starts the server as configured
   */
+  #[napi(ts_return_type = "Promise<void>")]
+  pub fn run(&mut self, env: Env) -> Result<JsObject, napi::Error> {
+    let fut = self.server.run().map(|(addresses, fut)| {
+      self.addresses = addresses.iter().map(|v| v.to_string()).collect();
+      fut
+    });
+    env.execute_tokio_future(
+      async move {
+        match fut {
+          Ok(v) => {
+            v.await;
+            Ok(Ok(()))
+          }
+          Err(err) => Ok(Err(err)),
+        }
+      },
+      |env, result: Result<(), ERPCError>| result.map_err(|err| to_js_error(env, err)),
+    )
+  }
+
+  /**
+    The addresses the server is bound to. Available once run has been called, e.g. to find out the port when listening on port 0
+  */
+  #[napi(getter)]
+  pub fn addresses(&self) -> Vec<String> {
+    self.addresses.clone()
+  }
+
+  /**
+    Replaces the served certificate without restarting the server. New connections use the new certificate.
+  */
   #[napi]
-  pub async fn run(&self) -> Result<(), napi::Error> {
-    let fut = match self.server.run() {
-      Ok(v) => v,
-      Err(err) => {
-        return Err(napi::Error::from_reason(format!(
-          "Could not start server: {err}"
-        )))
-      }
-    };
+  pub fn reload_tls(&self, env: Env, options: TlsOptions) -> Result<(), napi::Error> {
+    let (cert, key) = options.read()?;
+    self
+      .server
+      .reload_tls(&cert, &key)
+      .map_err(|err| to_js_error(&env, err))
+  }
 
-    fut.await;
-    Ok(())
+  /**
+    Calls the handler on every socket connected with the given role.
+    Resolves to the outcome of each call like Promise.allSettled, together with the id of the socket.
+  */
+  #[napi(ts_return_type = "Promise<Array<BroadcastResult>>")]
+  pub fn broadcast(
+    &self,
+    env: Env,
+    role: String,
+    method_identifier: String,
+    parameters: Option<Vec<JsUnknown>>,
+    timeout: Option<u32>,
+  ) -> Result<JsObject, napi::Error> {
+    let server = self.server.clone();
+    let parameters = parameters
+      .unwrap_or_default()
+      .into_iter()
+      .map(|v| from_js_value(&env, v))
+      .collect::<Result<Vec<_>, _>>()?;
+    let timeout = timeout.map(|v| Duration::from_millis(v.into()));
+
+    env.execute_tokio_future(
+      async move {
+        Ok(
+          server
+            .broadcast(&role, method_identifier, parameters, timeout)
+            .await,
+        )
+      },
+      |env, results| {
+        let results = results.map_err(|err| to_js_error(env, err))?;
+        to_broadcast_results(env, results)
+      },
+    )
+  }
+
+  /**
+    Subscribes a socket connected to this server to the topic
+  */
+  #[napi]
+  pub fn subscribe(&self, env: Env, socket_id: String, topic: String) -> Result<(), napi::Error> {
+    self
+      .server
+      .subscribe(&socket_id, &topic)
+      .map_err(|err| to_js_error(&env, err))
+  }
+
+  #[napi]
+  pub fn unsubscribe(&self, socket_id: String, topic: String) {
+    self.server.unsubscribe(&socket_id, &topic);
+  }
+
+  /**
+    Sends a notification to every socket subscribed to the topic, including sockets of servers sharing the pub/sub.
+    Returns the number of sockets it has been sent to.
+  */
+  #[napi]
+  pub fn publish(
+    &self,
+    env: Env,
+    topic: String,
+    method_identifier: String,
+    parameters: Option<Vec<JsUnknown>>,
+  ) -> Result<u32, napi::Error> {
+    let parameters = parameters
+      .unwrap_or_default()
+      .into_iter()
+      .map(|v| from_js_value(&env, v))
+      .collect::<Result<Vec<_>, _>>()?;
+    self
+      .server
+      .publish(&topic, method_identifier, parameters)
+      .map(|v| v as u32)
+      .map_err(|err| to_js_error(&env, err))
+  }
+
+  /**
+    Calls the handler on every socket subscribed to the topic and resolves to the outcome of each call, like broadcast
+  */
+  #[napi(ts_return_type = "Promise<Array<BroadcastResult>>")]
+  pub fn publish_call(
+    &self,
+    env: Env,
+    topic: String,
+    method_identifier: String,
+    parameters: Option<Vec<JsUnknown>>,
+    timeout: Option<u32>,
+  ) -> Result<JsObject, napi::Error> {
+    let server = self.server.clone();
+    let parameters = parameters
+      .unwrap_or_default()
+      .into_iter()
+      .map(|v| from_js_value(&env, v))
+      .collect::<Result<Vec<_>, _>>()?;
+    let timeout = timeout.map(|v| Duration::from_millis(v.into()));
+
+    env.execute_tokio_future(
+      async move {
+        Ok(
+          server
+            .publish_call(&topic, method_identifier, parameters, timeout)
+            .await,
+        )
+      },
+      |env, results| {
+        let results = results.map_err(|err| to_js_error(env, err))?;
+        to_broadcast_results(env, results)
+      },
+    )
+  }
+
+  /**
+    Shares the topic subscriptions with other servers using the same pub/sub. Must be called before run.
+  */
+  #[napi]
+  pub fn set_pub_sub(&mut self, pub_sub: &ERPCPubSub) {
+    self.server.set_pub_sub(Arc::new(pub_sub.pub_sub().clone()));
   }
 
   /**
    * Stops the server
    */
   #[napi]
-  pub fn stop(&self) -> Result<(), napi::Error> {
-    match self.server.stop() {
-      Ok(_) => Ok(()),
-      Err(err) => Err(napi::Error::from_reason(format!(
-        "Could not stop server: {err}"
-      ))),
-    }
+  pub fn stop(&self, env: Env) -> Result<(), napi::Error> {
+    self.server.stop().map_err(|err| to_js_error(&env, err))
   }
 }
@@ -0,0 +1,182 @@
+use std::sync::{Arc, Mutex};
+
+use napi::{bindgen_prelude::spawn, Env, JsFunction, NapiRaw, Status};
+
+use crate::erpc::{Socket, SocketEvent};
+use crate::error::to_js_error;
+use crate::threadsafe_function::{
+  ThreadSafeCallContext, ThreadsafeFunction, ThreadsafeFunctionCallMode,
+};
+
+type CloseEvent = (Option<u16>, String);
+
+#[derive(Default)]
+struct SocketCallbacks {
+  on_close: Option<ThreadsafeFunction<CloseEvent>>,
+  on_error: Option<ThreadsafeFunction<String>>,
+  /** Set once the connection has been closed, so late close callbacks still get called */
+  closed: Option<CloseEvent>,
+}
+
+/**
+  A websocket connection of a client, passed to the socket connection callback of the server
+*/
+#[napi(js_name = "ERPCSocket")]
+pub struct ERPCSocket {
+  socket: Socket,
+  callbacks: Arc<Mutex<SocketCallbacks>>,
+}
+
+impl ERPCSocket {
+  pub fn new(socket: Socket) -> Self {
+    let callbacks = Arc::new(Mutex::new(SocketCallbacks::default()));
+
+    let events = socket.events.clone();
+    let event_callbacks = callbacks.clone();
+    spawn(async move {
+      while let Ok(event) = events.recv_async().await {
+        let mut callbacks = match event_callbacks.lock() {
+          Ok(v) => v,
+          Err(err) => {
+            eprintln!("Could not dispatch socket event: {err}");
+            return;
+          }
+        };
+
+        match event {
+          SocketEvent::Error(message) => {
+            if let Some(tsf) = &callbacks.on_error {
+              tsf.call(message, ThreadsafeFunctionCallMode::NonBlocking);
+            }
+          }
+          SocketEvent::Close { code, reason } => {
+            if let Some(tsf) = &callbacks.on_close {
+              tsf.call(
+                (code, reason.clone()),
+                ThreadsafeFunctionCallMode::NonBlocking,
+              );
+            }
+            callbacks.closed = Some((code, reason));
+          }
+        }
+      }
+    });
+
+    ERPCSocket { socket, callbacks }
+  }
+
+  pub fn socket(&self) -> &Socket {
+    &self.socket
+  }
+
+  fn callbacks(&self) -> Result<std::sync::MutexGuard<'_, SocketCallbacks>, napi::Error> {
+    self
+      .callbacks
+      .lock()
+      .map_err(|err| napi::Error::from_reason(format!("Could not access socket callbacks: {err}")))
+  }
+}
+
+#[napi]
+impl ERPCSocket {
+  /**
+    Sockets can not be constructed, they are passed to the socket connection callback of the server
+  */
+  // napi only exports the class under its js_name when it has a constructor
+  #[napi(constructor)]
+  pub fn constructor() -> Result<Self, napi::Error> {
+    Err(napi::Error::new(
+      Status::GenericFailure,
+      "ERPCSocket can not be constructed, sockets are passed to the socket connection callback",
+    ))
+  }
+
+  /**
+    Unique id of this connection
+  */
+  #[napi(getter)]
+  pub fn id(&self) -> String {
+    self.socket.id.clone()
+  }
+
+  /**
+    The role the client connected with
+  */
+  #[napi(getter)]
+  pub fn role(&self) -> String {
+    self.socket.role.clone()
+  }
+
+  /**
+    The protocol version negotiated with the client during the handshake
+  */
+  #[napi(getter)]
+  pub fn protocol_version(&self) -> u32 {
+    self.socket.version
+  }
+
+  /**
+    Registers a callback which is called with the close code and reason once the connection is closed.
+    The code is undefined when the connection was closed without a close frame.
+  */
+  #[napi(skip_typescript)]
+  pub fn on_close(&self, env: Env, func: JsFunction) -> Result<(), napi::Error> {
+    let tsf = ThreadsafeFunction::create(
+      env.raw(),
+      unsafe { func.raw() },
+      0,
+      |ctx: ThreadSafeCallContext<CloseEvent>| {
+        let code = match ctx.value.0 {
+          Some(v) => ctx.env.create_uint32(v.into())?.into_unknown(),
+          None => ctx.env.get_undefined()?.into_unknown(),
+        };
+        let reason = ctx.env.create_string_from_std(ctx.value.1)?.into_unknown();
+        ctx.callback.call(None, &[code, reason])?;
+        Ok(())
+      },
+    )?;
+
+    let mut callbacks = self.callbacks()?;
+    if let Some(closed) = callbacks.closed.clone() {
+      tsf.call(closed, ThreadsafeFunctionCallMode::NonBlocking);
+    }
+    callbacks.on_close.replace(tsf);
+    Ok(())
+  }
+
+  /**
+    Registers a callback which is called with an error message when reading from or writing to the connection fails
+  */
+  #[napi(skip_typescript)]
+  pub fn on_error(&self, env: Env, func: JsFunction) -> Result<(), napi::Error> {
+    let tsf = ThreadsafeFunction::create(
+      env.raw(),
+      unsafe { func.raw() },
+      0,
+      |ctx: ThreadSafeCallContext<String>| {
+        let message = ctx.env.create_string_from_std(ctx.value)?;
+        ctx.callback.call(None, &[message])?;
+        Ok(())
+      },
+    )?;
+
+    self.callbacks()?.on_error.replace(tsf);
+    Ok(())
+  }
+
+  /**
+    Closes the connection. Defaults to the normal closure code 1000
+  */
+  #[napi]
+  pub fn close(
+    &self,
+    env: Env,
+    code: Option<u16>,
+    reason: Option<String>,
+  ) -> Result<(), napi::Error> {
+    self
+      .socket
+      .close(code.unwrap_or(1000), reason.unwrap_or_default())
+      .map_err(|err| to_js_error(&env, err))
+  }
+}
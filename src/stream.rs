@@ -0,0 +1,210 @@
+use std::sync::Arc;
+
+use futures_util::{Stream, StreamExt};
+use napi::{
+  bindgen_prelude::ToNapiValue, Env, JsFunction, JsObject, JsSymbol, JsUnknown, NapiValue,
+  ValueType,
+};
+use serde_json::Value;
+
+use crate::{
+  erpc::{error::ERPCError, server::ResponseStream},
+  error::{application_error_from_js, to_js_error},
+  value::{from_js_value, to_js_value},
+};
+
+/**
+  An item produced by a JS async iterator, None once it is done
+*/
+type IteratorEvent = Result<Option<Value>, ERPCError>;
+
+fn async_iterator_symbol(env: &Env) -> Result<JsSymbol, napi::Error> {
+  // Symbol is a function, so it has to be coerced to read its properties
+  env
+    .get_global()?
+    .get_named_property::<JsFunction>("Symbol")?
+    .coerce_to_object()?
+    .get_named_property("asyncIterator")
+}
+
+/**
+  Returns the async iterator of a value implementing the async iterable protocol, e.g. an async generator
+*/
+pub fn async_iterator(env: &Env, value: &JsUnknown) -> Result<Option<JsObject>, napi::Error> {
+  if value.get_type()? != ValueType::Object {
+    return Ok(None);
+  }
+
+  let object: JsObject = unsafe { value.cast() };
+  let factory: JsUnknown = object.get_property(async_iterator_symbol(env)?)?;
+  if factory.get_type()? != ValueType::Function {
+    return Ok(None);
+  }
+
+  let factory: JsFunction = unsafe { factory.cast() };
+  Ok(Some(unsafe {
+    factory.call_without_args(Some(&object))?.cast()
+  }))
+}
+
+/**
+  Reads a JS async iterator into a stream. Once the stream is dropped, return() is called on the iterator
+  when it produces its next item.
+*/
+pub fn from_async_iterator(env: &Env, iterator: JsObject) -> Result<ResponseStream, napi::Error> {
+  let (sender, reciever) = flume::unbounded::<IteratorEvent>();
+  pump(env, iterator, sender)?;
+
+  Ok(Box::pin(futures_util::stream::unfold(
+    Some(reciever),
+    |reciever| async move {
+      let reciever = reciever?;
+      match reciever.recv_async().await {
+        Ok(Ok(Some(v))) => Some((Ok(v), Some(reciever))),
+        Ok(Ok(None)) => None,
+        Ok(Err(err)) => Some((Err(err), None)),
+        Err(_) => Some((
+          Err(ERPCError::HandlerFailed(
+            "The stream ended unexpectedly".to_string(),
+          )),
+          None,
+        )),
+      }
+    },
+  )))
+}
+
+/**
+  Requests the next item of the iterator and sends it once the returned promise settles, then continues with the next one
+*/
+fn pump(
+  env: &Env,
+  iterator: JsObject,
+  sender: flume::Sender<IteratorEvent>,
+) -> Result<(), napi::Error> {
+  let next: JsFunction = iterator.get_named_property("next")?;
+  let step = match next.call_without_args(Some(&iterator)) {
+    Ok(v) => v,
+    Err(err) => {
+      let thrown =
+        unsafe { JsUnknown::from_raw(env.raw(), ToNapiValue::to_napi_value(env.raw(), err)?)? };
+      sender
+        .send(Err(ERPCError::Application(application_error_from_js(
+          env, thrown,
+        ))))
+        .ok();
+      return Ok(());
+    }
+  };
+
+  let fulfilled_sender = sender.clone();
+  let on_fulfilled = env.create_function_from_closure("onNext", move |ctx| {
+    let iterator: JsObject = ctx.this()?;
+    let step = || -> Result<Option<Value>, napi::Error> {
+      let result: JsObject = ctx.get(0)?;
+      let done = result
+        .get_named_property::<JsUnknown>("done")?
+        .coerce_to_bool()?
+        .get_value()?;
+      if done {
+        return Ok(None);
+      }
+      Ok(Some(from_js_value(
+        ctx.env,
+        result.get_named_property("value")?,
+      )?))
+    };
+
+    let item = match step() {
+      Ok(v) => v,
+      Err(err) => {
+        fulfilled_sender
+          .send(Err(ERPCError::HandlerFailed(err.reason)))
+          .ok();
+        return ctx.env.get_undefined();
+      }
+    };
+    let done = item.is_none();
+    if fulfilled_sender.send(Ok(item)).is_err() {
+      // the caller is gone, give the iterator the chance to clean up
+      let close: JsUnknown = iterator.get_named_property("return")?;
+      if close.get_type()? == ValueType::Function {
+        let close: JsFunction = unsafe { close.cast() };
+        close.call_without_args(Some(&iterator))?;
+      }
+    } else if !done {
+      pump(ctx.env, iterator, fulfilled_sender.clone())?;
+    }
+    ctx.env.get_undefined()
+  })?;
+  let on_rejected = env.create_function_from_closure("onRejected", move |ctx| {
+    let err = application_error_from_js(ctx.env, ctx.get::<JsUnknown>(0)?);
+    sender.send(Err(ERPCError::Application(err))).ok();
+    ctx.env.get_undefined()
+  })?;
+
+  // the iterator is bound as this, as the closure can not hold on to JS values
+  let on_fulfilled = on_fulfilled.coerce_to_object()?;
+  let bind: JsFunction = on_fulfilled.get_named_property("bind")?;
+  let on_fulfilled = bind.call(Some(&on_fulfilled), &[iterator])?;
+
+  // Promise.resolve handles iterators returning plain values instead of promises
+  let promise = env
+    .get_global()?
+    .get_named_property::<JsFunction>("Promise")?
+    .coerce_to_object()?;
+  let resolve: JsFunction = promise.get_named_property("resolve")?;
+  let step: JsObject = unsafe { resolve.call(Some(&promise), &[step])?.cast() };
+  let then: JsFunction = step.get_named_property("then")?;
+  then.call(Some(&step), &[on_fulfilled, on_rejected.into_unknown()])?;
+
+  Ok(())
+}
+
+/**
+  Exposes a stream as JS async iterator which can be consumed with for await.
+  Calling return(), e.g. by breaking out of the loop, drops the stream.
+*/
+pub fn to_async_iterator<S>(env: &Env, stream: S) -> Result<JsObject, napi::Error>
+where
+  S: Stream<Item = Result<Value, ERPCError>> + Send + 'static,
+{
+  let (stream, abort_handle) = futures_util::stream::abortable(stream);
+  let stream = Arc::new(tokio::sync::Mutex::new(Box::pin(stream)));
+
+  let next = env.create_function_from_closure("next", move |ctx| {
+    let stream = stream.clone();
+    ctx.env.execute_tokio_future(
+      async move { Ok(stream.lock().await.next().await) },
+      |env, item| match item {
+        Some(Ok(v)) => iterator_result(env, Some(to_js_value(env, &v)?)),
+        Some(Err(err)) => Err(to_js_error(env, err)),
+        None => iterator_result(env, None),
+      },
+    )
+  })?;
+  let close = env.create_function_from_closure("return", move |ctx| {
+    abort_handle.abort();
+    ctx
+      .env
+      .execute_tokio_future(async { Ok(()) }, |env, _| iterator_result(env, None))
+  })?;
+  let iterable =
+    env.create_function_from_closure("[Symbol.asyncIterator]", |ctx| ctx.this::<JsObject>())?;
+
+  let mut iterator = env.create_object()?;
+  iterator.set_named_property("next", next)?;
+  iterator.set_named_property("return", close)?;
+  iterator.set_property(async_iterator_symbol(env)?, iterable)?;
+  Ok(iterator)
+}
+
+fn iterator_result(env: &Env, value: Option<JsUnknown>) -> Result<JsObject, napi::Error> {
+  let mut result = env.create_object()?;
+  result.set_named_property("done", value.is_none())?;
+  match value {
+    Some(v) => result.set_named_property("value", v)?,
+    None => result.set_named_property("value", env.get_undefined()?)?,
+  };
+  Ok(result)
+}
@@ -1,32 +1,298 @@
-use napi::{Env, JsObject, JsUnknown};
+use std::{
+  future::Future,
+  pin::Pin,
+  sync::{Arc, Mutex},
+  time::Duration,
+};
 
+use futures_util::TryFutureExt;
+use napi::{
+  bindgen_prelude::spawn, Env, JsFunction, JsObject, JsTypeError, JsUnknown, NapiRaw, NapiValue,
+  Ref, Status,
+};
+use tokio::sync::oneshot;
+
+use crate::erpc::client::{InFlightPolicy, ReconnectPolicy};
+use crate::erpc::error::ERPCError;
+use crate::erpc::protocol::{encoding::Encoding, Request};
 use crate::erpc::target::TargetType;
-use crate::erpc::Socket;
+use crate::error::to_js_error;
+use crate::socket::ERPCSocket;
+use crate::stream::to_async_iterator;
+use crate::threadsafe_function::{
+  ThreadSafeCallContext, ThreadsafeFunction, ThreadsafeFunctionCallMode,
+};
+use crate::value::{from_js_value, to_js_value};
 
 #[napi(object)]
 pub struct TargetOptions {
   pub port: u16,
   pub address: String,
+  /**
+    Time in milliseconds to wait for a response before a call is rejected with a timeout error
+  */
+  pub timeout: Option<u32>,
+  /**
+    The encoding of requests to http servers, either "json" (default) or "msgpack"
+  */
+  pub encoding: Option<String>,
+  /**
+    The role socket server targets connect with, i.e. the server endpoint ws/{role}
+  */
+  pub role: Option<String>,
+  /**
+    How socket server targets reconnect after their connection dropped
+  */
+  pub reconnect: Option<ReconnectOptions>,
+  /**
+    Collects calls made in the same tick and sends them as a single batch request
+  */
+  pub batch: Option<bool>,
+}
+
+#[napi(object)]
+pub struct ReconnectOptions {
+  /**
+    Milliseconds before the first reconnection attempt, defaults to 100
+  */
+  pub initial_delay: Option<u32>,
+  /**
+    Maximum milliseconds between two attempts, defaults to 30000
+  */
+  pub max_delay: Option<u32>,
+  /**
+    The factor the delay grows by with every failed attempt, defaults to 2
+  */
+  pub multiplier: Option<f64>,
+  /**
+    The fraction of the delay which is randomized, between 0 and 1, defaults to 0.5
+  */
+  pub jitter: Option<f64>,
+  /**
+    Gives up after this many failed attempts in a row, retries forever by default
+  */
+  pub max_attempts: Option<u32>,
+  /**
+    What happens to calls waiting for a response when the connection drops.
+    "fail" (default) rejects them, "resend" sends them again once reconnected.
+  */
+  pub in_flight: Option<String>,
+}
+
+impl ReconnectOptions {
+  fn into_policy(self) -> Result<ReconnectPolicy, napi::Error> {
+    let mut policy = ReconnectPolicy::default();
+    if let Some(v) = self.initial_delay {
+      policy.initial_delay = Duration::from_millis(v.into());
+    }
+    if let Some(v) = self.max_delay {
+      policy.max_delay = Duration::from_millis(v.into());
+    }
+    if let Some(v) = self.multiplier {
+      policy.multiplier = v;
+    }
+    if let Some(v) = self.jitter {
+      policy.jitter = v;
+    }
+    policy.max_attempts = self.max_attempts;
+    policy.in_flight = match self.in_flight.as_deref() {
+      None | Some("fail") => InFlightPolicy::Fail,
+      Some("resend") => InFlightPolicy::Resend,
+      Some(v) => {
+        return Err(napi::Error::new(
+          Status::InvalidArg,
+          format!("Unsupported in flight policy {v}"),
+        ))
+      }
+    };
+    Ok(policy)
+  }
+}
+
+#[napi(object)]
+pub struct CallOptions {
+  /**
+    Time in milliseconds to wait for a response, overrides the timeout of the target
+  */
+  pub timeout: Option<u32>,
+  /**
+    Rejects the call with a cancelled error when aborted
+  */
+  #[napi(ts_type = "AbortSignal")]
+  pub signal: Option<JsObject>,
 }
 
+/**
+  Removes the abort listener of a settled call from its signal, so signals reused for many calls
+  neither keep finished calls alive nor cancel them when aborted later
+*/
+fn remove_abort_listener(
+  env: &Env,
+  mut signal: Ref<()>,
+  mut on_abort: Ref<()>,
+) -> Result<(), napi::Error> {
+  let remove = || -> Result<(), napi::Error> {
+    let signal: JsObject = env.get_reference_value(&signal)?;
+    let on_abort: JsFunction = env.get_reference_value(&on_abort)?;
+    let remove_event_listener: JsFunction = signal.get_named_property("removeEventListener")?;
+    remove_event_listener.call(
+      Some(&signal),
+      &[
+        env.create_string("abort")?.into_unknown(),
+        on_abort.into_unknown(),
+      ],
+    )?;
+    Ok(())
+  };
+  let removed = remove();
+  // the references are released even if the listener could not be removed
+  signal.unref(*env)?;
+  on_abort.unref(*env)?;
+  removed
+}
+
+/**
+  A call waiting for the next batch to be sent
+*/
+struct QueuedCall {
+  request: Request,
+  sender: oneshot::Sender<Result<serde_json::Value, ERPCError>>,
+}
+
+type BatchQueue = Arc<Mutex<Vec<QueuedCall>>>;
+
 #[napi(js_name = "ERPCTarget")]
 pub struct ERPCTarget {
   target: crate::erpc::target::ERPCTarget,
+  on_disconnected: Arc<Mutex<Option<ThreadsafeFunction<()>>>>,
+  /**
+    The calls of the current tick, when batching is enabled
+  */
+  batch: Option<BatchQueue>,
+}
+
+impl ERPCTarget {
+  /**
+    Adds a call to the batch of the current tick. The first call of a tick schedules sending the batch.
+  */
+  fn queue_call(
+    &self,
+    env: &Env,
+    batch: &BatchQueue,
+    request: Request,
+  ) -> Result<oneshot::Receiver<Result<serde_json::Value, ERPCError>>, napi::Error> {
+    let (sender, reciever) = oneshot::channel();
+    let mut queue = batch
+      .lock()
+      .map_err(|err| napi::Error::from_reason(format!("Could not access batch: {err}")))?;
+    queue.push(QueuedCall { request, sender });
+    if queue.len() > 1 {
+      return Ok(reciever);
+    }
+    drop(queue);
+
+    let t = self.target.clone();
+    let batch = batch.clone();
+    let flush = env.create_function_from_closure("flushBatch", move |ctx| {
+      let calls = match batch.lock() {
+        Ok(mut v) => std::mem::take(&mut *v),
+        Err(err) => {
+          return Err(napi::Error::from_reason(format!(
+            "Could not access batch: {err}"
+          )))
+        }
+      };
+      let t = t.clone();
+      spawn(async move {
+        let (requests, senders): (Vec<_>, Vec<_>) =
+          calls.into_iter().map(|v| (v.request, v.sender)).unzip();
+        match t.call_batch(requests).await {
+          Ok(results) => {
+            for (sender, result) in senders.into_iter().zip(results) {
+              sender.send(result).ok();
+            }
+          }
+          Err(err) => {
+            for sender in senders {
+              sender.send(Err(err.clone())).ok();
+            }
+          }
+        }
+      });
+      ctx.env.get_undefined()
+    })?;
+    // setImmediate runs after the current tick, including the promise callbacks it queued
+    let set_immediate: JsFunction = env.get_global()?.get_named_property("setImmediate")?;
+    set_immediate.call(None, &[flush])?;
+    Ok(reciever)
+  }
 }
 
 #[napi]
 impl ERPCTarget {
   #[napi(constructor)]
-  pub fn new(options: TargetOptions, target_type: String) -> Self {
+  pub fn new(env: Env, options: TargetOptions, target_type: String) -> Result<Self, napi::Error> {
     let target_type = match target_type.as_str() {
       "browser" => TargetType::Browser,
       "http-server" => TargetType::HTTPServer,
-      _ => panic!("Unsupported target type {}", target_type),
+      "socket-server" => TargetType::SocketServer,
+      _ => {
+        let err = JsTypeError::from(napi::Error::new(
+          Status::InvalidArg,
+          format!("Unsupported target type {target_type}"),
+        ));
+        return Err(napi::Error::from(err.into_unknown(env)));
+      }
     };
 
-    ERPCTarget {
-      target: crate::erpc::target::ERPCTarget::new(options.address, options.port, target_type),
+    let socket_server = matches!(target_type, TargetType::SocketServer);
+    let mut target =
+      crate::erpc::target::ERPCTarget::new(options.address, options.port, target_type);
+    target.set_timeout(options.timeout.map(|v| Duration::from_millis(v.into())));
+    if let Some(encoding) = options.encoding {
+      match Encoding::from_name(&encoding) {
+        Some(v) => target.set_encoding(v),
+        None => {
+          return Err(napi::Error::new(
+            Status::InvalidArg,
+            format!("Unsupported encoding {encoding}"),
+          ))
+        }
+      }
     }
+
+    if let Some(role) = options.role {
+      target.set_role(role);
+    }
+    if let Some(reconnect) = options.reconnect {
+      target.set_reconnect_policy(reconnect.into_policy()?);
+    }
+
+    let on_disconnected = Arc::new(Mutex::new(None::<ThreadsafeFunction<()>>));
+    if socket_server {
+      // socket server targets reconnect on their own, so disconnects are observed instead of awaited
+      let mut connection = target.watch_connection();
+      let on_disconnected = on_disconnected.clone();
+      spawn(async move {
+        while connection.changed().await.is_ok() {
+          if *connection.borrow_and_update() {
+            continue;
+          }
+          if let Some(tsf) = on_disconnected.lock().ok().and_then(|v| v.clone()) {
+            tsf.call((), ThreadsafeFunctionCallMode::NonBlocking);
+          }
+        }
+      });
+    }
+
+    Ok(ERPCTarget {
+      target,
+      on_disconnected,
+      batch: options
+        .batch
+        .unwrap_or(false)
+        .then(|| Arc::new(Mutex::new(Vec::new()))),
+    })
   }
 
   #[napi(skip_typescript)]
@@ -34,43 +300,230 @@ impl ERPCTarget {
     &self,
     env: Env,
     method_identifier: String,
-    parameters: Option<Vec<serde_json::Value>>,
+    parameters: Option<Vec<JsUnknown>>,
+    options: Option<CallOptions>,
   ) -> Result<JsObject, napi::Error> {
     let t = self.target.clone();
+    let parameters = parameters
+      .unwrap_or_default()
+      .into_iter()
+      .map(|v| from_js_value(&env, v))
+      .collect::<Result<Vec<_>, _>>()?;
+    let (timeout, signal) = match options {
+      Some(v) => (v.timeout, v.signal),
+      None => (None, None),
+    };
+    let timeout = match timeout {
+      Some(v) => Some(Duration::from_millis(v.into())),
+      None => t.timeout(),
+    };
 
-    env.execute_tokio_future(
-      async move {
-        let res: serde_json::Value = match t
-          .call(method_identifier, parameters.unwrap_or_default())
-          .await
-        {
-          Ok(v) => v,
-          Err(err) => {
-            return Err(napi::Error::from_reason(err));
+    let (abort_sender, abort_reciever) = oneshot::channel::<()>();
+    let mut abort_listener = None;
+    if let Some(signal) = signal {
+      if signal.get_named_property::<bool>("aborted")? {
+        abort_sender.send(()).ok();
+      } else {
+        let abort_sender = Mutex::new(Some(abort_sender));
+        let on_abort = env.create_function_from_closure("onAbort", move |ctx| {
+          if let Some(sender) = abort_sender.lock().ok().and_then(|mut v| v.take()) {
+            sender.send(()).ok();
           }
-        };
+          ctx.env.get_undefined()
+        })?;
+        let mut listener_options = env.create_object()?;
+        listener_options.set_named_property("once", true)?;
+        let add_event_listener: JsFunction = signal.get_named_property("addEventListener")?;
+        add_event_listener.call(
+          Some(&signal),
+          &[
+            env.create_string("abort")?.into_unknown(),
+            // the function is referenced again below to remove the listener
+            unsafe { JsUnknown::from_raw_unchecked(env.raw(), on_abort.raw()) },
+            listener_options.into_unknown(),
+          ],
+        )?;
+        abort_listener = Some((
+          env.create_reference(signal)?,
+          env.create_reference(on_abort)?,
+        ));
+      }
+    }
+
+    let call: Pin<Box<dyn Future<Output = Result<serde_json::Value, ERPCError>> + Send>> =
+      match &self.batch {
+        Some(batch) => {
+          let reciever = self.queue_call(
+            &env,
+            batch,
+            Request {
+              identifier: method_identifier,
+              parameters,
+            },
+          )?;
+          let call = async move {
+            reciever
+              .await
+              .unwrap_or_else(|_| Err(ERPCError::Internal("The batch was dropped".to_string())))
+          };
+          // the batch is sent anyways, timing out only stops waiting for the result
+          Box::pin(async move {
+            match timeout {
+              Some(timeout) => tokio::time::timeout(timeout, call)
+                .await
+                .map_err(|_| ERPCError::Timeout)?,
+              None => call.await,
+            }
+          })
+        }
+        None => Box::pin(async move {
+          t.call_with_timeout::<_, serde_json::Value>(method_identifier, parameters, timeout)
+            .await
+        }),
+      };
 
-        Ok(res)
+    env.execute_tokio_future(
+      async move {
+        // dropping the call on abort cleans up the pending request
+        Ok(tokio::select! {
+          v = call => v,
+          Ok(_) = abort_reciever => Err(ERPCError::Cancelled),
+        })
       },
-      |env, data| {
-        let ret: JsUnknown = env.to_js_value(&data)?;
-        Ok(ret)
+      move |env, data| {
+        if let Some((signal, on_abort)) = abort_listener {
+          remove_abort_listener(env, signal, on_abort)?;
+        }
+        let data = data.map_err(|err| to_js_error(env, err))?;
+        to_js_value(env, &data)
       },
     )
   }
 
+  /**
+    Sends a notification, which the receiver handles without responding.
+    Resolves once the notification has been accepted by the server or sent via socket.
+  */
+  #[napi(ts_return_type = "Promise<void>")]
+  pub fn notify(
+    &self,
+    env: Env,
+    method_identifier: String,
+    parameters: Option<Vec<JsUnknown>>,
+  ) -> Result<JsObject, napi::Error> {
+    let t = self.target.clone();
+    let parameters = parameters
+      .unwrap_or_default()
+      .into_iter()
+      .map(|v| from_js_value(&env, v))
+      .collect::<Result<Vec<_>, _>>()?;
+
+    env.execute_tokio_future(
+      async move { Ok(t.notify(method_identifier, parameters).await) },
+      |env, result| result.map_err(|err| to_js_error(env, err)),
+    )
+  }
+
+  /**
+    Calls a handler which streams its response, e.g. an async generator, and returns its items as async iterator.
+    Breaking out of a for await loop cancels the call.
+  */
+  #[napi(ts_return_type = "AsyncIterableIterator<any>")]
+  pub fn stream(
+    &self,
+    env: Env,
+    method_identifier: String,
+    parameters: Option<Vec<JsUnknown>>,
+  ) -> Result<JsObject, napi::Error> {
+    let parameters = parameters
+      .unwrap_or_default()
+      .into_iter()
+      .map(|v| from_js_value(&env, v))
+      .collect::<Result<Vec<_>, _>>()?;
+    let t = self.target.clone();
+    // the call is made once the first item is requested, failures are thrown from next()
+    let stream = async move { t.stream(method_identifier, parameters).await }.try_flatten_stream();
+    to_async_iterator(&env, stream)
+  }
+
   #[napi(skip_typescript, js_name = "setERPCSocket")]
-  pub fn set_erpc_socket(&self, env: Env, socket: JsObject) -> Result<(), napi::Error> {
+  pub fn set_erpc_socket(&self, env: Env, socket: &ERPCSocket) -> Result<(), napi::Error> {
     let mut t = self.target.clone();
-    let socket: &mut Socket = env.unwrap(&socket)?;
-    let socket = socket.clone();
+    let on_disconnected = self.on_disconnected.clone();
+    let socket = socket.socket().clone();
     env.execute_tokio_future(
       async move {
         t.listen_on_socket(socket).await;
+        if let Some(tsf) = on_disconnected.lock().ok().and_then(|v| v.clone()) {
+          tsf.call((), ThreadsafeFunctionCallMode::NonBlocking);
+        }
         Ok(())
       },
       |_, _| Ok(()),
     )?;
     Ok(())
   }
+
+  /**
+    Connects a socket server target to the ws/{role} endpoint of the server.
+    Resolves once connected, after which the target reconnects on its own whenever the connection drops.
+  */
+  #[napi(ts_return_type = "Promise<void>")]
+  pub fn connect(&self, env: Env) -> Result<JsObject, napi::Error> {
+    let t = self.target.clone();
+    env.execute_tokio_future(async move { Ok(t.connect().await) }, |env, result| {
+      result.map_err(|err| to_js_error(env, err))
+    })
+  }
+
+  /**
+    Closes the connection of a socket server target and stops reconnecting
+  */
+  #[napi]
+  pub fn close(&self, env: Env) -> Result<(), napi::Error> {
+    self.target.close().map_err(|err| to_js_error(&env, err))
+  }
+
+  /**
+    Registers a callback which is called when the socket of this target disconnects.
+    Calls waiting for a response via that socket are rejected with a socket closed error.
+  */
+  #[napi(skip_typescript)]
+  pub fn on_disconnected(&self, env: Env, func: JsFunction) -> Result<(), napi::Error> {
+    let tsf = ThreadsafeFunction::create(
+      env.raw(),
+      unsafe { func.raw() },
+      0,
+      |ctx: ThreadSafeCallContext<()>| {
+        ctx.callback.call_without_args(None)?;
+        Ok(())
+      },
+    )?;
+
+    match self.on_disconnected.lock() {
+      Ok(mut v) => {
+        v.replace(tsf);
+        Ok(())
+      }
+      Err(err) => Err(napi::Error::from_reason(format!(
+        "Could not set disconnect callback: {err}"
+      ))),
+    }
+  }
+
+  /**
+    Whether a socket is currently connected to this target
+  */
+  #[napi(getter)]
+  pub fn connected(&self) -> bool {
+    self.target.is_connected()
+  }
+
+  /**
+    The protocol version negotiated with the peer of the connected socket, undefined while not connected
+  */
+  #[napi(getter)]
+  pub fn protocol_version(&self) -> Option<u32> {
+    self.target.protocol_version()
+  }
 }
@@ -0,0 +1,79 @@
+use napi::{
+  bindgen_prelude::{FromNapiValue, Uint8Array},
+  Env, JsObject, JsUnknown, NapiRaw, ValueType,
+};
+use serde_json::{Map, Value};
+
+use crate::erpc::protocol::binary;
+
+/**
+  Converts a JS value into json like env.from_js_value, but keeps Buffers and Uint8Arrays as binary values
+*/
+pub fn from_js_value(env: &Env, value: JsUnknown) -> Result<Value, napi::Error> {
+  if value.get_type()? != ValueType::Object {
+    return env.from_js_value(value);
+  }
+
+  if value.is_buffer()? || value.is_typedarray()? {
+    if let Ok(v) = unsafe { Uint8Array::from_napi_value(env.raw(), value.raw()) } {
+      return Ok(binary::encode(&v));
+    }
+    return env.from_js_value(value);
+  }
+
+  if value.is_array()? {
+    let array: JsObject = unsafe { value.cast() };
+    return (0..array.get_array_length()?)
+      .map(|i| {
+        let element: JsUnknown = array.get_element(i)?;
+        match element.get_type()? {
+          ValueType::Undefined | ValueType::Function | ValueType::Symbol => Ok(Value::Null),
+          _ => from_js_value(env, element),
+        }
+      })
+      .collect::<Result<Vec<_>, _>>()
+      .map(Value::Array);
+  }
+
+  let object: JsObject = unsafe { value.cast() };
+  let names = object.get_property_names()?;
+  let mut map = Map::new();
+  for i in 0..names.get_array_length()? {
+    let name: String = env.from_js_value(names.get_element::<JsUnknown>(i)?)?;
+    let property: JsUnknown = object.get_named_property(&name)?;
+    match property.get_type()? {
+      ValueType::Undefined | ValueType::Function | ValueType::Symbol => {}
+      _ => {
+        map.insert(name, from_js_value(env, property)?);
+      }
+    }
+  }
+  Ok(Value::Object(map))
+}
+
+/**
+  Converts json into a JS value like env.to_js_value, but turns binary values into Buffers
+*/
+pub fn to_js_value(env: &Env, value: &Value) -> Result<JsUnknown, napi::Error> {
+  if let Some(data) = binary::decode(value) {
+    return Ok(env.create_buffer_with_data(data)?.into_unknown());
+  }
+
+  match value {
+    Value::Array(v) => {
+      let mut array = env.create_array_with_length(v.len())?;
+      for (i, element) in v.iter().enumerate() {
+        array.set_element(i as u32, to_js_value(env, element)?)?;
+      }
+      Ok(array.into_unknown())
+    }
+    Value::Object(v) => {
+      let mut object = env.create_object()?;
+      for (name, property) in v {
+        object.set_named_property(name, to_js_value(env, property)?)?;
+      }
+      Ok(object.into_unknown())
+    }
+    v => env.to_js_value(v),
+  }
+}